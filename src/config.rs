@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+/// Server wide settings shared with every worker.
+///
+/// Modify `HTTPServer.config` before calling `listen`, changes after that are ignored.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Maximum size in bytes of a request body buffered in memory by `body_bytes`, `body_text`,
+    /// `form` and `json`, bigger bodies are rejected with `413`.
+    pub max_body_size: usize,
+    /// Maximum size in bytes of a request body read through `body_reader`, bigger bodies are
+    /// rejected with `413`. Streamed bodies aren't kept in memory so there's no limit by default.
+    pub max_stream_size: u64,
+    /// Maximum length of the request line in bytes, longer ones are rejected with `414`.
+    pub max_request_line: usize,
+    /// Maximum number of request headers, more are rejected with `431`.
+    pub max_header_count: usize,
+    /// Maximum size of all the request header lines in bytes, bigger heads are rejected with `431`.
+    pub max_header_size: usize,
+    /// How long an idle keep-alive connection waits for the next request before it's closed.
+    pub keep_alive_timeout: Duration,
+    /// Time allowed to receive a whole request line and headers, slower clients get a `408`.
+    pub header_read_timeout: Duration,
+    /// How long a single read of the request body can wait for data.
+    pub body_read_timeout: Duration,
+    /// How long a single write of the response can block before the connection is dropped.
+    pub write_timeout: Duration,
+    /// Minimum average rate of a request body in bytes per second, measured over the time spent
+    /// waiting for it, slower bodies get a `408`. `0` disables the check.
+    pub min_transfer_rate: u64,
+    /// Maximum number of requests served on one connection, the last one is answered with `connection: close`.
+    pub max_requests_per_connection: usize,
+    /// How long `listen` waits for in-flight requests when shutting down before closing their connections.
+    pub shutdown_timeout: Duration,
+    /// Whether SIGINT and SIGTERM shut the server down gracefully while it's listening (unix only).
+    pub handle_signals: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            max_body_size: 1024 * 1024,
+            max_stream_size: u64::MAX,
+            max_request_line: 8 * 1024,
+            max_header_count: 100,
+            max_header_size: 16 * 1024,
+            keep_alive_timeout: Duration::from_secs(5),
+            header_read_timeout: Duration::from_secs(10),
+            body_read_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            min_transfer_rate: 512,
+            shutdown_timeout: Duration::from_secs(30),
+            handle_signals: true,
+            max_requests_per_connection: 100,
+        }
+    }
+}
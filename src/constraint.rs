@@ -0,0 +1,191 @@
+/// A restriction on the value of a route parameter, written as `{name:constraint}`.
+///
+/// Supported constraints are:
+/// - integer types like `u64` or `i32`, the value must parse as that type.
+/// - `uuid`, a hyphenated UUID like `67e55044-10b1-426f-9247-bb680e5fe0c8`.
+/// - a character class like `[a-z0-9-]+`, optionally negated with `^` and followed by
+///   `+`, `*`, `?`, `{n}`, `{n,}` or `{n,m}`. Without a quantifier it matches one character.
+/// - a list of values like `en|fr|de`, anything else is a list of one value.
+pub(crate) enum Constraint {
+    Integer(fn(&str) -> bool),
+    Uuid,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+        min: usize,
+        max: Option<usize>,
+    },
+    OneOf(Vec<String>),
+}
+
+impl Constraint {
+    /// Parses the part after the `:` in `{name:constraint}`.
+    ///
+    /// ## Fails:
+    /// with a description of the problem if a character class or its quantifier is malformed.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        if let Some(check) = integer(source) {
+            return Ok(Constraint::Integer(check));
+        }
+        if source == "uuid" {
+            return Ok(Constraint::Uuid);
+        }
+        if let Some(class) = source.strip_prefix('[') {
+            return parse_class(class);
+        }
+        if source.is_empty() || source.split('|').any(str::is_empty) {
+            return Err(format!("Empty value in constraint {source:?}"));
+        }
+
+        Ok(Constraint::OneOf(
+            source.split('|').map(str::to_string).collect(),
+        ))
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            Constraint::Integer(check) => check(value),
+            Constraint::Uuid => is_uuid(value),
+            Constraint::Class {
+                ranges,
+                negated,
+                min,
+                max,
+            } => {
+                let count = value.chars().count();
+                count >= *min
+                    && max.is_none_or(|max| count <= max)
+                    && value.chars().all(|c| {
+                        ranges.iter().any(|(low, high)| (*low..=*high).contains(&c)) != *negated
+                    })
+            }
+            Constraint::OneOf(values) => values.iter().any(|val| val == value),
+        }
+    }
+}
+
+/// Returns a check for the integer type named `name`.
+fn integer(name: &str) -> Option<fn(&str) -> bool> {
+    let check: fn(&str) -> bool = match name {
+        "u8" => |v| v.parse::<u8>().is_ok(),
+        "u16" => |v| v.parse::<u16>().is_ok(),
+        "u32" => |v| v.parse::<u32>().is_ok(),
+        "u64" => |v| v.parse::<u64>().is_ok(),
+        "u128" => |v| v.parse::<u128>().is_ok(),
+        "usize" => |v| v.parse::<usize>().is_ok(),
+        "i8" => |v| v.parse::<i8>().is_ok(),
+        "i16" => |v| v.parse::<i16>().is_ok(),
+        "i32" => |v| v.parse::<i32>().is_ok(),
+        "i64" => |v| v.parse::<i64>().is_ok(),
+        "i128" => |v| v.parse::<i128>().is_ok(),
+        "isize" => |v| v.parse::<isize>().is_ok(),
+        _ => return None,
+    };
+
+    Some(check)
+}
+
+/// Parses a character class after its `[` and the quantifier after its `]`.
+fn parse_class(source: &str) -> Result<Constraint, String> {
+    let (class, quantifier) = source
+        .split_once(']')
+        .ok_or(format!("Unclosed character class [{source}"))?;
+    let (negated, class) = match class.strip_prefix('^') {
+        Some(class) => (true, class),
+        None => (false, class),
+    };
+
+    let chars = class.chars().collect::<Vec<_>>();
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        // a `-` at the start or the end is a literal.
+        if i + 2 < chars.len() && chars[i + 1] == '-' {
+            if chars[i] > chars[i + 2] {
+                return Err(format!("Invalid range {}-{}", chars[i], chars[i + 2]));
+            }
+            ranges.push((chars[i], chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((chars[i], chars[i]));
+            i += 1;
+        }
+    }
+    if ranges.is_empty() {
+        return Err("Empty character class".to_string());
+    }
+
+    let (min, max) = match quantifier {
+        "" => (1, Some(1)),
+        "+" => (1, None),
+        "*" => (0, None),
+        "?" => (0, Some(1)),
+        _ => {
+            let bounds = quantifier
+                .strip_prefix('{')
+                .and_then(|q| q.strip_suffix('}'))
+                .ok_or(format!("Invalid quantifier {quantifier:?}"))?;
+            let number = |n: &str| {
+                n.parse::<usize>()
+                    .map_err(|_| format!("Invalid quantifier {quantifier:?}"))
+            };
+
+            match bounds.split_once(',') {
+                None => (number(bounds)?, Some(number(bounds)?)),
+                Some((min, "")) => (number(min)?, None),
+                Some((min, max)) => (number(min)?, Some(number(max)?)),
+            }
+        }
+    };
+    if max.is_some_and(|max| max < min) {
+        return Err(format!("Invalid quantifier {quantifier:?}"));
+    }
+
+    Ok(Constraint::Class {
+        ranges,
+        negated,
+        min,
+        max,
+    })
+}
+
+/// Whether `value` is a hyphenated UUID (8-4-4-4-12 hex digits).
+fn is_uuid(value: &str) -> bool {
+    let groups = value.split('-').map(str::len).collect::<Vec<_>>();
+
+    groups == [8, 4, 4, 4, 12] && value.chars().all(|c| c == '-' || c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod constraint_tests {
+    use super::*;
+
+    fn matches(constraint: &str, value: &str) -> bool {
+        Constraint::parse(constraint).unwrap().matches(value)
+    }
+
+    #[test]
+    fn checks_values() {
+        assert!(matches("u64", "42"));
+        assert!(!matches("u64", "-1"));
+        assert!(!matches("u8", "256"));
+        assert!(matches("i32", "-7"));
+
+        assert!(matches("uuid", "67e55044-10b1-426f-9247-bb680e5fe0c8"));
+        assert!(!matches("uuid", "67e55044-10b1-426f-9247-bb680e5fe0c"));
+
+        assert!(matches("[a-z0-9-]+", "my-post-2"));
+        assert!(!matches("[a-z0-9-]+", "My-Post"));
+        assert!(matches("[A-Z]{3}", "USD"));
+        assert!(!matches("[A-Z]{3}", "US"));
+        assert!(matches("[^.]{1,}", "file"));
+        assert!(!matches("[^.]{1,}", "file.txt"));
+
+        assert!(matches("en|fr", "fr"));
+        assert!(!matches("en|fr", "de"));
+
+        assert!(Constraint::parse("[a-z").is_err());
+        assert!(Constraint::parse("[z-a]").is_err());
+        assert!(Constraint::parse("[a-z]{x}").is_err());
+    }
+}
@@ -1,84 +1,183 @@
 use std::error::Error;
-use std::sync::RwLockReadGuard;
+use std::panic::{self, AssertUnwindSafe};
 
+use crate::error::{HttpError, Panicked};
+use crate::method::Method;
 use crate::request::Request;
 use crate::response::Response;
-use crate::router::Route;
-use crate::Routes;
+use crate::router::{Params, Route, RouteHandler, RouteResult, Routes};
 
-pub fn handle_requests(mut req: Request, mut res: Response, routes: RwLockReadGuard<Routes>) {
-    let method = req.method.clone();
+pub fn handle_requests(req: &mut Request, res: &mut Response, routes: &Routes) {
+    // a method nothing was registered for can't be served at all.
+    if !req.method.is_standard() && !routes.methods.contains_key(&req.method) {
+        let error = HttpError::new(501, &format!("Unknown method {}", req.method));
+        return handle_errors(req, res, routes, error.into());
+    }
+
+    if let Err(e) = run_handlers(req, res, routes) {
+        return handle_errors(req, res, routes, e);
+    }
+
+    // nothing answered the request.
+    if !res.is_committed() {
+        let allowed = routes.allowed(&req.path);
+        let result = if allowed.is_empty()
+            || (req.method != Method::Options && allowed.contains(&req.method))
+        {
+            call(&routes.not_found, req, res).map(|_| ())
+        } else {
+            answer_allowed(req, res, &allowed)
+        };
+
+        if let Err(e) = result {
+            handle_errors(req, res, routes, e);
+        }
+    }
+}
 
+/// Answers a request to a path that has routes for other methods with the `allow` header,
+/// `OPTIONS` requests get a `204` and others fail with a `405`.
+fn answer_allowed(
+    req: &mut Request,
+    res: &mut Response,
+    allowed: &[Method],
+) -> Result<(), Box<dyn Error>> {
+    let allow = allowed
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    res.set_header("allow", &allow)?;
+
+    if req.method != Method::Options {
+        return Err(HttpError::new(405, "Method Not Allowed").into());
+    }
+    res.set_status(204)?;
+    res.writer()?.finish()?;
+    Ok(())
+}
+
+/// Runs the global handlers, the routes and the finals until one of them ends the response.
+fn run_handlers(
+    req: &mut Request,
+    res: &mut Response,
+    routes: &Routes,
+) -> Result<(), Box<dyn Error>> {
     // Apply the global handlers first.
-    for route in routes.get("global").unwrap().iter() {
-        if route.path == req.path || route.path == "*" {
-            let handle = &route.handler;
-            let rslt = handle(&mut req, &mut res);
-
-            if let Err(e) = rslt {
-                return handle_errors(req, res, routes, e);
-            } else {
-                if rslt.unwrap() {
-                    return;
-                }
-            }
+    for route in routes.global.iter() {
+        if (route.path == req.path || route.path == "*") && call(route, req, res)? {
+            return Ok(());
         }
     }
 
-    for route in routes.get(method.as_str()).unwrap().iter() {
-        if route.path == req.path {
-            let handle = &route.handler;
-            let rslt = handle(&mut req, &mut res);
-
-            if let Err(e) = rslt {
-                return handle_errors(req, res, routes, e);
-            } else {
-                if rslt.unwrap() {
-                    return;
-                }
-            }
+    for (route, params) in find_routes(req, routes) {
+        req.params = params;
+        if call(route, req, res)? {
+            return Ok(());
         }
     }
+    req.params.clear();
 
-    for route in routes.get("final").unwrap().iter() {
-        if route.path == req.path || route.path == "*" {
-            let handle = &route.handler;
-            let rslt = handle(&mut req, &mut res);
-
-            if let Err(e) = rslt {
-                return handle_errors(req, res, routes, e);
-            } else {
-                if rslt.unwrap() {
-                    return;
-                }
-            }
+    for route in routes.finals.iter() {
+        if (route.path == req.path || route.path == "*") && call(route, req, res)? {
+            return Ok(());
         }
     }
+
+    Ok(())
+}
+
+/// Returns the routes matching the method and path of `req`, best first.
+///
+/// `HEAD` requests use the `GET` routes when no `HEAD` route matches, the body is left out.
+fn find_routes<'a>(req: &Request, routes: &'a Routes) -> Vec<(&'a Route, Params)> {
+    let find = |method: &Method| {
+        routes
+            .methods
+            .get(method)
+            .map(|tree| tree.find(&req.path))
+            .unwrap_or_default()
+    };
+
+    match find(&req.method) {
+        found if found.is_empty() && req.method == Method::Head => find(&Method::Get),
+        found => found,
+    }
 }
 
-fn handle_errors(
-    mut req: Request,
-    mut res: Response,
-    routes: RwLockReadGuard<Routes>,
-    error: Box<dyn Error>,
-) {
-    let error_routes = routes.get("errors").unwrap();
-    let error_routes = error_routes
+fn handle_errors(req: &mut Request, res: &mut Response, routes: &Routes, error: Box<dyn Error>) {
+    let error_routes = routes
+        .errors
         .iter()
         .filter(|r| r.path == req.path || r.path == "*")
-        .filter(|r| r.method == req.method || r.method == "*")
+        .filter(|r| req.method == r.method || r.method == "*")
         .collect::<Vec<&Route>>();
 
     req.error = Some(error);
 
     for route in error_routes {
-        let handler = &route.handler;
-        let rslt = handler(&mut req, &mut res);
+        match call(route, req, res) {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(e) => keep_panic(req, e),
+        }
+    }
 
-        if let Ok(val) = rslt {
-            if val {
-                return;
-            }
+    // none of the error handlers answered the request.
+    if !res.is_committed() {
+        if let Err(e) = call(&routes.unhandled_error, req, res) {
+            keep_panic(req, e);
         }
     }
 }
+
+/// Runs the handler of `route`, a panic is caught and returned as a `Panicked` error.
+///
+/// The connection isn't reused after a panic since the response may be half written.
+fn call(route: &Route, req: &mut Request, res: &mut Response) -> RouteResult {
+    panic::catch_unwind(AssertUnwindSafe(|| (route.handler)(req, res))).unwrap_or_else(|payload| {
+        res.close_connection();
+        Err(Box::new(Panicked::from_payload(payload)))
+    })
+}
+
+/// Errors of error handlers are ignored, but a panic is kept in `req.error` so it's reported,
+/// unless the request already failed with one.
+fn keep_panic(req: &mut Request, error: Box<dyn Error>) {
+    let panicked = |e: &Option<Box<dyn Error>>| e.as_deref().is_some_and(|e| e.is::<Panicked>());
+
+    if error.is::<Panicked>() && !panicked(&req.error) {
+        req.error = Some(error);
+    }
+}
+
+/// The default handler for requests that no route answered, sends a `404`.
+pub fn default_not_found() -> RouteHandler {
+    Box::new(|_req, res| {
+        res.set_status(404)?;
+        res.send("Not Found")?;
+        Ok(true)
+    })
+}
+
+/// The default handler for errors that no error handler answered.
+///
+/// Sends the status of an `HttpError` (like a `413` for a big body) or a `500` for any other error.
+pub fn default_unhandled_error() -> RouteHandler {
+    Box::new(|req, res| {
+        let status = req
+            .error
+            .as_deref()
+            .and_then(HttpError::status_of)
+            .unwrap_or(500);
+
+        // the rest of the body can't be read after these, so the connection can't be reused.
+        if matches!(status, 408 | 413) || req.body_failed() {
+            res.close_connection();
+        }
+        res.set_status(status)?;
+        let message = res.status_message().to_string();
+        res.send(message)?;
+        Ok(true)
+    })
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error as err, ErrorKind};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A cookie to be sent with `Response::set_cookie`.
+///
+/// Attributes are set using the builder methods:
+/// ```
+/// use stress::cookie::{Cookie, SameSite};
+///
+/// let cookie = Cookie::new("session", "abc")
+///     .path("/")
+///     .max_age(3600)
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    /// Lifetime in seconds, `0` or less removes the cookie.
+    pub max_age: Option<i64>,
+    pub expires: Option<SystemTime>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// Creates a cookie that tells the client to remove `name`.
+    ///
+    /// The path and domain must match the ones the cookie was set with.
+    pub fn removal(name: &str) -> Self {
+        Cookie::new(name, "").expire()
+    }
+
+    /// Turns this into a removal cookie by clearing its value and expiring it.
+    pub fn expire(mut self) -> Self {
+        self.value.clear();
+        self.max_age = Some(0);
+        self.expires = Some(UNIX_EPOCH);
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn max_age(mut self, seconds: i64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn expires(mut self, time: SystemTime) -> Self {
+        self.expires = Some(time);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Sets the `SameSite` attribute, `SameSite::None` also requires `secure`.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Checks that the cookie can be written to a `Set-Cookie` header.
+    ///
+    /// ## Fails:
+    /// if the name isn't a token or the value or attributes have invalid characters.
+    pub fn validate(&self) -> Result<(), err> {
+        let is_token = |c: char| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c);
+        let is_octet = |c: char| c.is_ascii_graphic() && !"\",;\\".contains(c);
+        let is_attribute = |val: &Option<String>| {
+            val.as_ref().is_none_or(|val| {
+                val.chars()
+                    .all(|c| c.is_ascii() && !c.is_ascii_control() && c != ';')
+            })
+        };
+
+        let value = self
+            .value
+            .strip_prefix('"')
+            .and_then(|val| val.strip_suffix('"'))
+            .unwrap_or(&self.value);
+
+        if self.name.is_empty()
+            || !self.name.chars().all(is_token)
+            || !value.chars().all(is_octet)
+            || !is_attribute(&self.path)
+            || !is_attribute(&self.domain)
+        {
+            return Err(err::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid cookie {}", self.name),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats the cookie as the value of a `Set-Cookie` header.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.max(0))?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http_date(expires))?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => f.write_str("; SameSite=Strict")?,
+            Some(SameSite::Lax) => f.write_str("; SameSite=Lax")?,
+            Some(SameSite::None) => f.write_str("; SameSite=None")?,
+            None => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a `Cookie` request header like `a=1; b="2"`.
+///
+/// If a name is repeated the first one is kept, pairs without a `=` are ignored.
+pub fn parse(header: &str) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+
+    for pair in header.split(';') {
+        if let Some((name, value)) = pair.split_once('=') {
+            let name = name.trim();
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|val| val.strip_suffix('"'))
+                .unwrap_or(value);
+
+            if !name.is_empty() {
+                cookies
+                    .entry(name.to_string())
+                    .or_insert_with(|| value.to_string());
+            }
+        }
+    }
+
+    cookies
+}
+
+/// Formats `time` as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // converts days since the epoch to a civil date (Howard Hinnant's algorithm).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod cookie_tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_http_dates() {
+        let date = |secs| http_date(UNIX_EPOCH + Duration::from_secs(secs));
+
+        assert_eq!(http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(date(784111777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(date(951782400), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(date(4102444799), "Thu, 31 Dec 2099 23:59:59 GMT");
+    }
+
+    #[test]
+    fn formats_set_cookie() {
+        let cookie = Cookie::new("session", "abc")
+            .path("/")
+            .domain("example.com")
+            .max_age(3600)
+            .expires(UNIX_EPOCH + Duration::from_secs(784111777))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+
+        assert_eq!(
+            cookie.to_string(),
+            "session=abc; Path=/; Domain=example.com; Max-Age=3600; \
+             Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; HttpOnly; SameSite=Lax"
+        );
+        assert_eq!(
+            Cookie::removal("session").to_string(),
+            "session=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+        assert!(cookie.validate().is_ok());
+        assert!(Cookie::new("bad name", "x").validate().is_err());
+        assert!(Cookie::new("a", "x;y").validate().is_err());
+    }
+
+    #[test]
+    fn parses_cookie_headers() {
+        let cookies = parse(r#"a=1; b="two words"; a=3; flag; c="#);
+
+        assert_eq!(cookies["a"], "1");
+        assert_eq!(cookies["b"], "two words");
+        assert_eq!(cookies["c"], "");
+        assert!(!cookies.contains_key("flag"));
+        assert_eq!(cookies.len(), 3);
+    }
+}
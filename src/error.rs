@@ -0,0 +1,82 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+/// An error that should be answered with a specific status code.
+///
+/// It can be wrapped inside an `io::Error` so it passes through the existing error paths,
+/// use `HttpError::status_of` to get the status back.
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new(status: u16, message: &str) -> Self {
+        HttpError {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    /// Looks for an `HttpError` in `error` or inside it if it's an `io::Error`.
+    ///
+    /// ## Returns:
+    /// The status code of the error or `None`
+    pub fn status_of(error: &(dyn Error + 'static)) -> Option<u16> {
+        if let Some(e) = error.downcast_ref::<HttpError>() {
+            return Some(e.status);
+        }
+
+        error
+            .downcast_ref::<io::Error>()
+            .and_then(|e| e.get_ref())
+            .and_then(|inner| inner.downcast_ref::<HttpError>())
+            .map(|e| e.status)
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.message)
+    }
+}
+
+impl Error for HttpError {}
+
+impl From<HttpError> for io::Error {
+    fn from(value: HttpError) -> Self {
+        io::Error::new(ErrorKind::InvalidData, value)
+    }
+}
+
+/// A caught panic, returned in place of a handler result and answered with a `500`.
+#[derive(Debug)]
+pub struct Panicked {
+    pub message: String,
+}
+
+impl Panicked {
+    /// Extracts the message from the payload of a caught panic.
+    pub fn from_payload(payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload
+                .downcast_ref::<&str>()
+                .map_or("Box<dyn Any>", |message| message)
+                .to_string(),
+        };
+
+        Panicked { message }
+    }
+}
+
+impl fmt::Display for Panicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Panicked: {}", self.message)
+    }
+}
+
+impl Error for Panicked {}
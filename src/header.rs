@@ -0,0 +1,187 @@
+use std::io::{Error as err, ErrorKind};
+
+/// An ordered header map with case-insensitive names.
+///
+/// Names keep the case they were inserted with and a name can hold multiple values,
+/// `get` returns the first one and `get_all` returns all of them in insertion order.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap::default()
+    }
+
+    /// Returns the first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, val)| val)
+    }
+
+    /// Returns every value of `name` in insertion order.
+    pub fn get_all(&self, name: &str) -> Vec<&String> {
+        self.entries
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, val)| val)
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets `name` to `value`, replacing any previous values.
+    ///
+    /// The header keeps the position of its first occurrence.
+    ///
+    /// ## Fails:
+    /// if the name or the value are invalid.
+    pub fn insert(&mut self, name: &str, value: &str) -> Result<(), err> {
+        validate(name, value)?;
+
+        match self
+            .entries
+            .iter()
+            .position(|(key, _)| key.eq_ignore_ascii_case(name))
+        {
+            Some(index) => {
+                self.entries[index].1 = value.to_string();
+
+                let mut rest = self.entries.split_off(index + 1);
+                rest.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+                self.entries.append(&mut rest);
+            }
+            None => self.entries.push((name.to_string(), value.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Adds `value` to `name` without removing the previous values.
+    ///
+    /// ## Fails:
+    /// if the name or the value are invalid.
+    pub fn append(&mut self, name: &str, value: &str) -> Result<(), err> {
+        validate(name, value)?;
+
+        self.entries.push((name.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    /// Removes every value of `name`.
+    ///
+    /// ## Returns:
+    /// `true` if the header was present.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+
+        len != self.entries.len()
+    }
+
+    /// Iterates over all the headers in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, val)| (key.as_str(), val.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Whether `name` is a valid header name (an RFC 9110 token).
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Whether `value` is a valid header value, it can't contain control characters like CR or LF.
+pub fn is_valid_value(value: &str) -> bool {
+    value.bytes().all(|b| b == b'\t' || !b.is_ascii_control())
+}
+
+fn validate(name: &str, value: &str) -> Result<(), err> {
+    if !is_valid_name(name) {
+        return Err(err::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid header name {name:?}"),
+        ));
+    }
+    if !is_valid_value(value) {
+        return Err(err::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid value for header {name}"),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod header_tests {
+    use super::*;
+
+    fn entries(headers: &HeaderMap) -> Vec<(&str, &str)> {
+        headers.iter().collect()
+    }
+
+    #[test]
+    fn ignores_name_case() {
+        let mut headers = HeaderMap::new();
+        headers.append("Set-Cookie", "a=1").unwrap();
+        headers.append("content-type", "text/plain").unwrap();
+        headers.append("set-cookie", "b=2").unwrap();
+
+        assert_eq!(headers.get("SET-COOKIE").unwrap(), "a=1");
+        assert_eq!(headers.get_all("set-cookie"), ["a=1", "b=2"]);
+        assert!(headers.contains("Content-Type"));
+        assert!(headers.get("accept").is_none());
+        assert!(headers.get_all("accept").is_empty());
+    }
+
+    #[test]
+    fn replaces_and_removes() {
+        let mut headers = HeaderMap::new();
+        headers.append("Vary", "accept").unwrap();
+        headers.append("x-id", "1").unwrap();
+        headers.append("vary", "origin").unwrap();
+
+        // the first position is kept and the other values are dropped.
+        headers.insert("VARY", "*").unwrap();
+        assert_eq!(entries(&headers), [("Vary", "*"), ("x-id", "1")]);
+        headers.insert("x-new", "2").unwrap();
+        assert_eq!(headers.len(), 3);
+
+        assert!(headers.remove("X-ID"));
+        assert!(!headers.remove("x-id"));
+        assert_eq!(entries(&headers), [("Vary", "*"), ("x-new", "2")]);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let mut headers = HeaderMap::new();
+
+        assert!(headers.insert("x-a", "one\r\nx-b: two").is_err());
+        assert!(headers.append("x-a", "one\ntwo").is_err());
+        assert!(headers.insert("", "value").is_err());
+        assert!(headers.insert("bad name", "value").is_err());
+        assert!(headers.append("bad:name", "value").is_err());
+        assert!(headers.is_empty());
+
+        assert!(headers.insert("x-tab", "a\tb").is_ok());
+    }
+}
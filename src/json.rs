@@ -0,0 +1,545 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Write};
+
+/// A JSON value.
+///
+/// Numbers are stored as `f64`, so integers above 2^53 lose precision.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// Returns the value of `key` if this is an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Returns the number if it's an integer that fits in an `i64`.
+    pub fn as_i64(&self) -> Option<i64> {
+        let val = self.as_f64()?;
+
+        if val.fract() == 0.0 && val >= i64::MIN as f64 && val <= i64::MAX as f64 {
+            Some(val as i64)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Value::Object(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::Array(value)
+    }
+}
+
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(value: BTreeMap<String, Value>) -> Self {
+        Value::Object(value)
+    }
+}
+
+/// Serializes the value as compact JSON.
+///
+/// `NaN` and infinite numbers can't be represented and are written as `null`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(val) => write!(f, "{val}"),
+            Value::Number(val) if !val.is_finite() => f.write_str("null"),
+            Value::Number(val) => write!(f, "{val}"),
+            Value::String(val) => write_string(f, val),
+            Value::Array(vals) => {
+                f.write_char('[')?;
+                for (i, val) in vals.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{val}")?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(map) => {
+                f.write_char('{')?;
+                for (i, (key, val)) in map.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{val}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, val: &str) -> fmt::Result {
+    f.write_char('"')?;
+
+    for c in val.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+
+    f.write_char('"')
+}
+
+/// Returned when the input isn't valid JSON or exceeds the parser limits.
+#[derive(Debug)]
+pub struct JsonError {
+    pub message: String,
+    /// Byte offset in the input where the error was found.
+    pub position: usize,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for JsonError {}
+
+/// Parses `input` using the default limits of `Parser`.
+pub fn parse(input: &str) -> Result<Value, JsonError> {
+    Parser::default().parse(input)
+}
+
+/// A strict RFC 8259 parser.
+///
+/// No trailing commas, comments or other extensions are accepted.
+#[derive(Debug, Clone)]
+pub struct Parser {
+    /// Maximum nesting of arrays and objects.
+    pub max_depth: usize,
+    /// Maximum size of the input in bytes.
+    pub max_size: usize,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser {
+            max_depth: 64,
+            max_size: 1024 * 1024,
+        }
+    }
+}
+
+impl Parser {
+    pub fn parse(&self, input: &str) -> Result<Value, JsonError> {
+        if input.len() > self.max_size {
+            return Err(JsonError {
+                message: String::from("Input is too large"),
+                position: self.max_size,
+            });
+        }
+
+        let mut state = State {
+            input: input.as_bytes(),
+            pos: 0,
+            depth: 0,
+            max_depth: self.max_depth,
+        };
+
+        let value = state.value()?;
+        state.skip_whitespace();
+
+        if state.pos < state.input.len() {
+            return Err(state.error("Unexpected trailing characters"));
+        }
+
+        Ok(value)
+    }
+}
+
+struct State<'a> {
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl State<'_> {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError {
+            message: message.to_string(),
+            position: self.pos,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str, value: Value) -> Result<Value, JsonError> {
+        if self.input[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("Invalid literal"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, JsonError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(b'n') => self.expect("null", Value::Null),
+            Some(b't') => self.expect("true", Value::Bool(true)),
+            Some(b'f') => self.expect("false", Value::Bool(false)),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b'[') => self.nested(State::array),
+            Some(b'{') => self.nested(State::object),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Value, JsonError>,
+    ) -> Result<Value, JsonError> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return Err(self.error("Maximum nesting depth exceeded"));
+        }
+
+        let value = parse(self)?;
+        self.depth -= 1;
+
+        Ok(value)
+    }
+
+    fn array(&mut self) -> Result<Value, JsonError> {
+        self.pos += 1;
+        let mut vals = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(vals));
+        }
+
+        loop {
+            vals.push(self.value()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(vals));
+                }
+                _ => return Err(self.error("Expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, JsonError> {
+        self.pos += 1;
+        let mut map = BTreeMap::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(map));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("Expected a string key"));
+            }
+            let key = self.string()?;
+
+            self.skip_whitespace();
+            if self.peek() != Some(b':') {
+                return Err(self.error("Expected ':'"));
+            }
+            self.pos += 1;
+
+            map.insert(key, self.value()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(map));
+                }
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.pos += 1;
+        let mut val = String::new();
+
+        loop {
+            let start = self.pos;
+            while let Some(byte) = self.peek() {
+                if byte == b'"' || byte == b'\\' || byte < 0x20 {
+                    break;
+                }
+                self.pos += 1;
+            }
+            // the input is a `str` and the run stops at ASCII bytes, so this is always valid.
+            val.push_str(std::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default());
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(val);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    val.push(self.escape()?);
+                }
+                Some(_) => return Err(self.error("Unescaped control character in string")),
+                None => return Err(self.error("Unterminated string")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, JsonError> {
+        let byte = self.peek().ok_or(self.error("Unterminated string"))?;
+        self.pos += 1;
+
+        let c = match byte {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let high = self.hex4()?;
+
+                if (0xD800..0xDC00).contains(&high) {
+                    if !self.input[self.pos..].starts_with(b"\\u") {
+                        return Err(self.error("Unpaired surrogate"));
+                    }
+                    self.pos += 2;
+
+                    let low = self.hex4()?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(self.error("Unpaired surrogate"));
+                    }
+
+                    let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                    char::from_u32(code).ok_or(self.error("Invalid unicode escape"))?
+                } else {
+                    char::from_u32(high).ok_or(self.error("Unpaired surrogate"))?
+                }
+            }
+            _ => return Err(self.error("Invalid escape")),
+        };
+
+        Ok(c)
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+            .ok_or(self.error("Invalid unicode escape"))?;
+
+        let code = digits.iter().fold(0, |code, digit| {
+            code * 16 + (*digit as char).to_digit(16).unwrap_or(0)
+        });
+        self.pos += 4;
+
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Value, JsonError> {
+        let start = self.pos;
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(self.error("Invalid number")),
+        }
+
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(self.error("Invalid number"));
+            }
+            self.digits();
+        }
+
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(self.error("Invalid number"));
+            }
+            self.digits();
+        }
+
+        let text = std::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default();
+        let number = text
+            .parse::<f64>()
+            .map_err(|_| self.error("Invalid number"))?;
+
+        if !number.is_finite() {
+            return Err(self.error("Number is out of range"));
+        }
+
+        Ok(Value::Number(number))
+    }
+
+    fn digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod json_tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_serializes() {
+        let value =
+            parse(r#" {"a": [1, -2.5e1, true, null], "b": "x\"\u00e9\ud83d\ude00\n"} "#).unwrap();
+
+        assert_eq!(
+            value.get("a").unwrap().as_array().unwrap()[1],
+            Value::Number(-25.0)
+        );
+        assert_eq!(value.get("b").unwrap().as_str(), Some("x\"é😀\n"));
+        assert_eq!(
+            value.to_string(),
+            r#"{"a":[1,-25,true,null],"b":"x\"é😀\n"}"#
+        );
+        assert_eq!(parse(&value.to_string()).unwrap(), value);
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        for input in [
+            "[1,]",
+            "01",
+            "{'a':1}",
+            "\"\t\"",
+            "[1] 2",
+            "\"\\ud800\"",
+            "1.",
+            "-",
+            "nul",
+            "",
+        ] {
+            assert!(parse(input).is_err(), "{input} should fail");
+        }
+
+        let deep = "[".repeat(65) + &"]".repeat(65);
+        assert!(parse(&deep).is_err());
+    }
+}
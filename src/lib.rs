@@ -1,39 +1,57 @@
 use std::collections::HashMap;
-use std::io::{Error, ErrorKind};
+use std::io::Error;
 use std::net::TcpListener;
 use std::sync::Arc;
 use std::sync::RwLock;
 
+pub mod config;
+mod constraint;
 mod controller;
+pub mod cookie;
+pub mod error;
+pub mod header;
+pub mod json;
+pub mod method;
 pub mod mime_types;
+pub mod multipart;
 mod pool;
+pub mod query;
 pub mod request;
 pub mod response;
 pub mod router;
+pub mod shutdown;
 pub mod status_codes;
+pub mod uri;
+
+#[cfg(test)]
+mod testing;
 
 /// Public module that contains built in middlewares for different purposes.
 ///
 /// this will likely will stay almost empty for like forever.
 pub mod middlewares;
 
-use pool::{ErrorHandler, ThreadPool};
+use config::ServerConfig;
+use method::Method;
+use pool::ThreadPool;
+pub use pool::{ErrorContext, ErrorHandler, PanicHandler};
 use request::Request;
 use response::Response;
-use router::{Route, RouteHandler};
-
-type Routes = HashMap<&'static str, Vec<Route>>;
+use router::{fallback, Route, RouteHandler, Router, Routes};
+use shutdown::ShutdownHandle;
 
 pub struct HTTPServer {
     pub addr: Option<&'static str>,
+    /// Settings passed to the workers when `listen` is called.
+    pub config: ServerConfig,
     thread_pool: ThreadPool,
     status_codes: Arc<HashMap<u16, String>>,
     mime_map: Arc<HashMap<&'static str, &'static str>>,
     ///
-    /// Uses a Hashmap to store the routes based on their method.
+    /// Holds a radix tree of routes for every method.
     ///
-    // consider using a tree data structure for pathnames later.
     routes: Arc<RwLock<Routes>>,
+    shutdown: ShutdownHandle,
 }
 
 impl HTTPServer {
@@ -45,19 +63,12 @@ impl HTTPServer {
     pub fn new(workers: usize) -> Self {
         HTTPServer {
             addr: None,
+            config: ServerConfig::default(),
             thread_pool: ThreadPool::new(workers),
             status_codes: Arc::new(import_status_messages()),
             mime_map: Arc::new(import_mime_map()),
-            routes: Arc::new(RwLock::new(HashMap::from([
-                ("errors", Vec::new()),
-                ("final", Vec::new()),
-                ("global", Vec::new()),
-                ("GET", Vec::new()),
-                ("POST", Vec::new()),
-                ("PUT", Vec::new()),
-                ("DELETE", Vec::new()),
-                ("PATCH", Vec::new()),
-            ]))),
+            routes: Arc::new(RwLock::new(Routes::default())),
+            shutdown: ShutdownHandle::default(),
         }
     }
 
@@ -72,18 +83,60 @@ impl HTTPServer {
     pub fn listen(&mut self, addr: &'static str) -> Result<(), Error> {
         let listener = TcpListener::bind(addr)?;
         self.addr = Some(addr);
-        self.thread_pool
-            .init(self.mime_map.clone(), self.status_codes.clone());
+        self.shutdown.set_addr(listener.local_addr().ok());
+        self.thread_pool.init(
+            self.mime_map.clone(),
+            self.status_codes.clone(),
+            Arc::new(self.config.clone()),
+            self.shutdown.clone(),
+        );
+        let watcher = self
+            .config
+            .handle_signals
+            .then(|| shutdown::watch_signals(self.shutdown.clone()));
+
+        let mut result = Ok(());
+        // a shutdown requested before the address was set had no listener to wake up.
+        if !self.shutdown.is_shutdown() {
+            for connection in listener.incoming() {
+                if self.shutdown.is_shutdown() {
+                    break;
+                }
+
+                match connection {
+                    Ok(stream) => self
+                        .thread_pool
+                        .execute(stream, self.routes.clone())
+                        .unwrap(),
+                    Err(error) => {
+                        result = Err(error);
+                        break;
+                    }
+                }
+            }
+        }
 
-        for connection in listener.incoming() {
-            let stream = connection?;
+        // stops accepting, then lets the workers finish what they have.
+        self.shutdown.set_addr(None);
+        self.shutdown.shutdown();
+        drop(listener);
+        self.thread_pool.shutdown(self.config.shutdown_timeout);
 
-            self.thread_pool
-                .execute(stream, self.routes.clone())
-                .unwrap();
+        if let Some(watcher) = watcher {
+            watcher.join().unwrap_or_default();
         }
+        self.shutdown.reset();
 
-        Ok(())
+        result
+    }
+
+    /// Returns a handle that stops the server from another thread.
+    ///
+    /// After a shutdown `listen` stops accepting connections, waits up to `config.shutdown_timeout`
+    /// for the requests being served, closes idle connections and returns once every worker stopped.
+    /// A shutdown requested before `listen` makes it return right away.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
     }
 
     // Route Initializers..
@@ -94,104 +147,127 @@ impl HTTPServer {
     /// Not intended for use unless you want some custom functionality.
     ///
     /// instead use `get`, `post`, `put` or `delete`, based on your target method.
+    ///
+    /// `path` can capture segments with `:name` and the rest of the path with `*name`,
+    /// the values are available with `req.param("name")`.
+    /// Static segments are matched first, then parameters and catch-alls.
+    ///
+    /// Standard methods can be given in any case, extension methods are case-sensitive
+    /// like the request method.
     pub fn register(&mut self, method: &'static str, path: &'static str, handler: RouteHandler) {
         self.routes
             .write()
             .unwrap()
-            .get_mut(method.to_uppercase().as_str())
-            .unwrap()
-            .push(Route {
-                handler,
+            .methods
+            .entry(Method::from_route(method))
+            .or_default()
+            .insert(
                 path,
-                method,
-            });
+                Route {
+                    handler,
+                    path,
+                    method,
+                },
+            );
+    }
+
+    /// Adds the routes of `router` under `prefix`, like `server.mount("/api", router)`.
+    ///
+    /// The router's handlers see `req.path` without the prefix and its middlewares only run
+    /// for requests under it. A `/` route of the router answers the prefix itself (`/api`),
+    /// not `/api/`.
+    ///
+    /// Panics if `prefix` has parameters like `:id`, `*rest` or `{id:u64}`.
+    pub fn mount(&mut self, prefix: &str, router: Router) {
+        self.routes.write().unwrap().mount(prefix, router);
     }
 
     /// Creates a global middleware for the specified `path`.
     ///
     /// global middlewares are always excuted before any route handlers.
     pub fn middleware(&mut self, path: &'static str, handler: RouteHandler) {
-        self.routes
-            .write()
-            .unwrap()
-            .get_mut("global")
-            .unwrap()
-            .push(Route {
-                handler,
-                path,
-                method: "*",
-            });
+        self.routes.write().unwrap().global.push(Route {
+            handler,
+            path,
+            method: "*",
+        });
     }
 
     pub fn last(&mut self, path: &'static str, handler: RouteHandler) {
-        self.routes
-            .write()
-            .unwrap()
-            .get_mut("final")
-            .unwrap()
-            .push(Route {
-                handler,
-                path,
-                method: "*",
-            });
+        self.routes.write().unwrap().finals.push(Route {
+            handler,
+            path,
+            method: "*",
+        });
     }
 
     pub fn error_ware(&mut self, method: &'static str, path: &'static str, handler: RouteHandler) {
-        self.routes
-            .write()
-            .unwrap()
-            .get_mut("errors")
-            .unwrap()
-            .push(Route {
-                handler,
-                path,
-                method,
-            });
+        self.routes.write().unwrap().errors.push(Route {
+            handler,
+            path,
+            method,
+        });
     }
 
     /// A wrapper for `HTTPServer.register()`.
     pub fn get(&mut self, path: &'static str, handler: RouteHandler) {
-        self.register("get", path, handler);
+        self.register("GET", path, handler);
     }
 
     /// A wrapper for `HTTPServer.register()`.
     pub fn post(&mut self, path: &'static str, handler: RouteHandler) {
-        self.register("post", path, handler);
+        self.register("POST", path, handler);
     }
 
     /// A wrapper for `HTTPServer.register()`.
     pub fn put(&mut self, path: &'static str, handler: RouteHandler) {
-        self.register("put", path, handler);
+        self.register("PUT", path, handler);
     }
 
     /// A wrapper for `HTTPServer.register()`.
     pub fn delete(&mut self, path: &'static str, handler: RouteHandler) {
-        self.register("delete", path, handler);
+        self.register("DELETE", path, handler);
     }
 
     /// A wrapper for `HTTPServer.register()`.
     pub fn patch(&mut self, path: &'static str, handler: RouteHandler) {
-        self.register("patch", path, handler);
+        self.register("PATCH", path, handler);
+    }
+
+    /// Replaces the handler used when no route answers a request, by default it sends a `404`.
+    pub fn on_not_found(&mut self, handler: RouteHandler) {
+        self.routes.write().unwrap().not_found = fallback(handler);
+    }
+
+    /// Replaces the handler used when a handler fails and no `error_ware` answers the request.
+    ///
+    /// By default it sends the status of an `error::HttpError` or a `500`,
+    /// the error is available in `req.error`.
+    pub fn on_unhandled_error(&mut self, handler: RouteHandler) {
+        self.routes.write().unwrap().unhandled_error = fallback(handler);
     }
 
     ///
     /// Sets an Error Handler if any error happens during req parsing.
     ///
-    pub fn on_error<F>(&mut self, f: ErrorHandler) {
+    /// The client was already answered with the status of the error when it has one,
+    /// the `ErrorContext` holds the peer address and the raw request line if it was read.
+    ///
+    pub fn on_error(&mut self, f: ErrorHandler) {
         self.thread_pool.error_handler = f;
     }
-}
 
-// Some helpers:
-
-fn extract_option<T>(op: Option<T>) -> Result<T, Error> {
-    if let Some(data) = op {
-        Ok(data)
-    } else {
-        Err(Error::new(ErrorKind::InvalidInput, "Empty Option"))
+    /// Sets the handler called when a route handler panics.
+    ///
+    /// Panics are caught so the worker survives, the client gets a `500` from the error handlers
+    /// and the connection is closed. By default the panic is printed to stderr.
+    pub fn on_panic(&mut self, f: PanicHandler) {
+        self.thread_pool.panic_handler = f;
     }
 }
 
+// Some helpers:
+
 fn import_status_messages() -> HashMap<u16, String> {
     let mut map = HashMap::new();
 
@@ -206,7 +282,7 @@ fn import_mime_map() -> HashMap<&'static str, &'static str> {
     mime_types::MimeTypes::get_map()
 }
 
-fn extract_ext<'a>(path: &'a str) -> &'a str {
+fn extract_ext(path: &str) -> &str {
     path.split(".")
         .collect::<Vec<&str>>()
         .pop()
@@ -0,0 +1,110 @@
+use std::fmt;
+
+/// The method of a request.
+///
+/// Methods are case-sensitive, anything that isn't one of the standard methods
+/// (like the WebDAV `PROPFIND`) is kept as an `Extension`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    Extension(String),
+}
+
+impl Method {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH",
+            Method::Extension(method) => method,
+        }
+    }
+
+    /// Whether this is a method every server is expected to know, not an `Extension`.
+    pub fn is_standard(&self) -> bool {
+        !matches!(self, Method::Extension(_))
+    }
+
+    /// Parses the method a route is registered for.
+    ///
+    /// Standard methods are matched ignoring case so `"post"` registers a `POST` route,
+    /// extension methods are kept as written.
+    pub(crate) fn from_route(method: &str) -> Self {
+        match Method::from(method.to_ascii_uppercase().as_str()) {
+            Method::Extension(_) => Method::Extension(method.to_string()),
+            standard => standard,
+        }
+    }
+}
+
+impl From<&str> for Method {
+    fn from(method: &str) -> Self {
+        match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "CONNECT" => Method::Connect,
+            "OPTIONS" => Method::Options,
+            "TRACE" => Method::Trace,
+            "PATCH" => Method::Patch,
+            method => Method::Extension(method.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<str> for Method {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Method {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+#[cfg(test)]
+mod method_tests {
+    use super::*;
+
+    #[test]
+    fn parses_methods() {
+        assert_eq!(Method::from("GET"), Method::Get);
+        assert_eq!(
+            Method::from("PROPFIND"),
+            Method::Extension("PROPFIND".to_string())
+        );
+        assert!(!Method::from("get").is_standard());
+        assert_eq!(Method::Head, "HEAD");
+        assert_eq!(Method::from("MKCOL").to_string(), "MKCOL");
+
+        assert_eq!(Method::from_route("post"), Method::Post);
+        assert_eq!(
+            Method::from_route("propfind"),
+            Method::Extension("propfind".to_string())
+        );
+    }
+}
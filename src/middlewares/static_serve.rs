@@ -6,7 +6,7 @@ pub fn serve_static(path: &'static str) -> RouteHandler {
     let path = path::Path::new(path);
     Box::new(move |req, res| {
         let target = path;
-        let mut filepath = target.join(&req.path.strip_prefix("/").unwrap_or_else(|| "/"));
+        let mut filepath = target.join(req.path.strip_prefix("/").unwrap_or("/"));
 
         if filepath == target {
             filepath = target.join(PathBuf::from("./index.html"));
@@ -15,7 +15,7 @@ pub fn serve_static(path: &'static str) -> RouteHandler {
         #[cfg(debug_assertions)]
         dbg!(&filepath);
 
-        if filepath.starts_with(&target) && filepath.exists() {
+        if filepath.starts_with(target) && filepath.exists() {
             let string_path = if let Some(path) = filepath.to_str() {
                 path
             } else {
@@ -0,0 +1,581 @@
+use std::fs::{self, File};
+use std::io::{self, prelude::*, Error as err};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::HttpError;
+use crate::query::QueryMap;
+use crate::request::Request;
+
+/// Limits applied while parsing a `multipart/form-data` body.
+///
+/// The body is read as a stream, so `config.max_body_size` doesn't apply,
+/// only `config.max_stream_size` and these.
+#[derive(Debug, Clone)]
+pub struct MultipartLimits {
+    /// Maximum size of a single file part.
+    pub max_file_size: u64,
+    /// Maximum size of a single text field, these are kept in memory.
+    pub max_field_size: usize,
+    /// Maximum size of all the parts together.
+    pub max_total_size: u64,
+    /// Maximum number of parts.
+    pub max_parts: usize,
+    /// Maximum size of the headers of a single part.
+    pub max_header_size: usize,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        MultipartLimits {
+            max_file_size: 64 * 1024 * 1024,
+            max_field_size: 64 * 1024,
+            max_total_size: 256 * 1024 * 1024,
+            max_parts: 128,
+            max_header_size: 8 * 1024,
+        }
+    }
+}
+
+/// A file part that was streamed to a temporary file.
+///
+/// The file is deleted when this is dropped, use `persist` to keep it.
+#[derive(Debug)]
+pub struct FilePart {
+    /// The form field name.
+    pub name: String,
+    /// The file name sent by the client, don't use it as a path without sanitizing it.
+    pub filename: String,
+    pub content_type: String,
+    /// Where the content is stored.
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+impl FilePart {
+    /// Moves the temporary file to `to` so it's not deleted.
+    pub fn persist<P: AsRef<Path>>(mut self, to: P) -> Result<(), err> {
+        if fs::rename(&self.path, &to).is_err() {
+            fs::copy(&self.path, &to)?;
+            fs::remove_file(&self.path)?;
+        }
+
+        self.path = PathBuf::new();
+        Ok(())
+    }
+}
+
+impl Drop for FilePart {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A parsed `multipart/form-data` body.
+#[derive(Debug, Default)]
+pub struct Multipart {
+    /// Text fields, every name can hold multiple values.
+    pub fields: QueryMap,
+    pub files: Vec<FilePart>,
+}
+
+impl Multipart {
+    /// Reads a `multipart/form-data` body from `req`.
+    ///
+    /// The body is streamed from the connection, text fields are collected in memory
+    /// and file parts are written to temporary files.
+    ///
+    /// ## Fails:
+    /// With a `415` if the request isn't multipart, a `400` if the body is malformed
+    /// or a `413` if any of the `limits` is exceeded.
+    pub fn parse(req: &mut Request, limits: &MultipartLimits) -> Result<Self, err> {
+        if !req.is_content_type("multipart/form-data") {
+            return Err(HttpError::new(415, "Expected a multipart form").into());
+        }
+
+        let boundary = req
+            .get_header("Content-Type")
+            .and_then(|val| content_param(val, "boundary"))
+            .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+            .ok_or(HttpError::new(400, "Missing multipart boundary"))?;
+
+        let mut parser = Parser {
+            reader: PartReader::new(req.body_reader()),
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            limits,
+            total: 0,
+        };
+
+        parser.run()
+    }
+
+    /// Returns the first value of the text field `name`.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name)?.first().map(|val| val.as_str())
+    }
+
+    /// Returns the first file sent as `name`.
+    pub fn file(&self, name: &str) -> Option<&FilePart> {
+        self.files.iter().find(|file| file.name == name)
+    }
+}
+
+struct Parser<'a, R> {
+    reader: PartReader<R>,
+    delimiter: Vec<u8>,
+    limits: &'a MultipartLimits,
+    /// bytes of part content read so far.
+    total: u64,
+}
+
+impl<R: Read> Parser<'_, R> {
+    fn run(&mut self) -> Result<Multipart, err> {
+        let mut form = Multipart::default();
+        let mut parts = 0;
+
+        // the preamble is discarded.
+        self.reader
+            .copy_until(&self.delimiter, &mut io::sink(), u64::MAX)?;
+
+        while !self.after_delimiter()? {
+            parts += 1;
+            if parts > self.limits.max_parts {
+                return Err(HttpError::new(413, "Too many multipart parts").into());
+            }
+
+            let headers = self.read_headers()?;
+            let disposition = find_header(&headers, "Content-Disposition")
+                .filter(|val| media_type(val).eq_ignore_ascii_case("form-data"))
+                .ok_or(HttpError::new(400, "Part without form-data disposition"))?;
+            let name = content_param(disposition, "name")
+                .ok_or(HttpError::new(400, "Part without a name"))?;
+            let filename = match content_param(disposition, "filename*") {
+                Some(encoded) => Some(decode_ext_value(&encoded)?),
+                None => content_param(disposition, "filename"),
+            };
+
+            match filename {
+                Some(filename) => {
+                    let content_type = find_header(&headers, "Content-Type")
+                        .unwrap_or("application/octet-stream")
+                        .to_string();
+                    let file = self.read_file(name, filename, content_type)?;
+                    form.files.push(file);
+                }
+                None => {
+                    let mut value = Vec::new();
+                    let limit = self.limits.max_field_size as u64;
+                    self.read_content(&mut value, limit, "Multipart field is too large")?;
+
+                    let value = String::from_utf8(value)
+                        .map_err(|_| HttpError::new(400, "Multipart field is not valid UTF-8"))?;
+                    form.fields.entry(name).or_default().push(value);
+                }
+            }
+        }
+
+        // the epilogue is discarded as well.
+        io::copy(&mut self.reader, &mut io::sink())?;
+
+        Ok(form)
+    }
+
+    /// Reads what follows a delimiter.
+    ///
+    /// ## Returns:
+    /// `true` if it was the closing delimiter.
+    fn after_delimiter(&mut self) -> Result<bool, err> {
+        while self.reader.buf.len() < 2 && self.reader.fill()? {}
+        if self.reader.buf.starts_with(b"--") {
+            return Ok(true);
+        }
+
+        let line = self.reader.read_line(self.limits.max_header_size)?;
+        if line.iter().any(|byte| !matches!(byte, b' ' | b'\t')) {
+            return Err(HttpError::new(400, "Malformed multipart delimiter").into());
+        }
+
+        Ok(false)
+    }
+
+    fn read_headers(&mut self) -> Result<Vec<(String, String)>, err> {
+        let mut headers = Vec::new();
+        let mut size = 0;
+
+        loop {
+            let line = self.reader.read_line(self.limits.max_header_size)?;
+            if line.is_empty() {
+                return Ok(headers);
+            }
+
+            size += line.len();
+            if size > self.limits.max_header_size {
+                return Err(HttpError::new(431, "Multipart headers are too large").into());
+            }
+
+            let line = String::from_utf8(line)
+                .map_err(|_| HttpError::new(400, "Malformed multipart header"))?;
+            let (key, val) = line
+                .split_once(':')
+                .ok_or(HttpError::new(400, "Malformed multipart header"))?;
+            headers.push((key.trim().to_string(), val.trim().to_string()));
+        }
+    }
+
+    fn read_file(
+        &mut self,
+        name: String,
+        filename: String,
+        content_type: String,
+    ) -> Result<FilePart, err> {
+        let path = temp_path();
+        let file = File::options().write(true).create_new(true).open(&path)?;
+
+        // the part owns the file from here on and removes it if reading fails.
+        let mut part = FilePart {
+            name,
+            filename,
+            content_type,
+            path,
+            size: 0,
+        };
+        let mut file = io::BufWriter::new(file);
+        let limit = self.limits.max_file_size;
+        part.size = self.read_content(&mut file, limit, "Multipart file is too large")?;
+        file.flush()?;
+
+        Ok(part)
+    }
+
+    /// Copies the current part to `sink`, enforcing `limit` and the total limit.
+    fn read_content<W: Write>(
+        &mut self,
+        sink: &mut W,
+        limit: u64,
+        message: &str,
+    ) -> Result<u64, err> {
+        let remaining = self.limits.max_total_size.saturating_sub(self.total);
+        let size = self
+            .reader
+            .copy_until(&self.delimiter, sink, limit.min(remaining))
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::FileTooLarge if remaining < limit => {
+                    HttpError::new(413, "Multipart body is too large").into()
+                }
+                io::ErrorKind::FileTooLarge => HttpError::new(413, message).into(),
+                _ => e,
+            })?;
+
+        self.total += size;
+        Ok(size)
+    }
+}
+
+/// A small buffered reader that can search for the multipart delimiter across reads.
+struct PartReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> PartReader<R> {
+    fn new(inner: R) -> Self {
+        // the first delimiter isn't preceded by a CRLF, adding one lets it match like the others.
+        PartReader {
+            inner,
+            buf: b"\r\n".to_vec(),
+        }
+    }
+
+    /// Reads more data into the buffer.
+    ///
+    /// ## Returns:
+    /// `false` if the body ended.
+    fn fill(&mut self) -> Result<bool, err> {
+        let mut chunk = [0; 8192];
+        let read = self.inner.read(&mut chunk)?;
+        self.buf.extend_from_slice(&chunk[..read]);
+
+        Ok(read > 0)
+    }
+
+    /// Reads a CRLF terminated line without the CRLF.
+    fn read_line(&mut self, max: usize) -> Result<Vec<u8>, err> {
+        loop {
+            if let Some(index) = find(&self.buf, b"\r\n") {
+                let line = self.buf[..index].to_vec();
+                self.buf.drain(..index + 2);
+                return Ok(line);
+            }
+
+            if self.buf.len() > max {
+                return Err(HttpError::new(431, "Multipart headers are too large").into());
+            }
+            if !self.fill()? {
+                return Err(HttpError::new(400, "Unexpected end of multipart body").into());
+            }
+        }
+    }
+
+    /// Copies bytes to `sink` until `delimiter`, which is consumed but not copied.
+    ///
+    /// Fails with `FileTooLarge` if more than `limit` bytes come before the delimiter.
+    fn copy_until<W: Write>(
+        &mut self,
+        delimiter: &[u8],
+        sink: &mut W,
+        limit: u64,
+    ) -> Result<u64, err> {
+        let mut copied = 0;
+
+        loop {
+            let (end, found) = match find(&self.buf, delimiter) {
+                Some(index) => (index, true),
+                // keep enough bytes to match a delimiter split between two reads.
+                None => (self.buf.len().saturating_sub(delimiter.len() - 1), false),
+            };
+
+            copied += end as u64;
+            if copied > limit {
+                return Err(err::new(io::ErrorKind::FileTooLarge, "Part is too large"));
+            }
+
+            sink.write_all(&self.buf[..end])?;
+
+            if found {
+                self.buf.drain(..end + delimiter.len());
+                return Ok(copied);
+            }
+
+            self.buf.drain(..end);
+            if !self.fill()? {
+                return Err(HttpError::new(400, "Unexpected end of multipart body").into());
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for PartReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            return self.inner.read(buf);
+        }
+
+        let len = buf.len().min(self.buf.len());
+        buf[..len].copy_from_slice(&self.buf[..len]);
+        self.buf.drain(..len);
+
+        Ok(len)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, val)| val.as_str())
+}
+
+/// The part of a header value before any parameters.
+fn media_type(val: &str) -> &str {
+    val.split(';').next().unwrap_or_default().trim()
+}
+
+/// Finds the parameter `name` in a header value like `form-data; name="field"`.
+///
+/// Quoted values can contain `;` and backslash escapes.
+fn content_param(val: &str, name: &str) -> Option<String> {
+    let mut rest = val.split_once(';')?.1;
+
+    loop {
+        let (key, after) = rest.split_once('=')?;
+        let key = key.trim();
+        let after = after.trim_start();
+
+        let (value, next) = if let Some(quoted) = after.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+
+            while let Some((index, c)) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        end = index + 1;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+
+            let next = quoted[end..].split_once(';').map(|(_, next)| next);
+            (value, next)
+        } else {
+            match after.split_once(';') {
+                Some((value, next)) => (value.trim().to_string(), Some(next)),
+                None => (after.trim().to_string(), None),
+            }
+        };
+
+        if key.eq_ignore_ascii_case(name) {
+            return Some(value);
+        }
+
+        rest = next?;
+    }
+}
+
+/// Decodes an RFC 5987 value like `UTF-8''caf%C3%A9.txt`.
+fn decode_ext_value(val: &str) -> Result<String, err> {
+    let mut pieces = val.splitn(3, '\'');
+    let charset = pieces.next().unwrap_or_default();
+    let encoded = pieces
+        .nth(1)
+        .filter(|_| charset.eq_ignore_ascii_case("UTF-8"))
+        .ok_or(HttpError::new(400, "Unsupported filename encoding"))?;
+
+    crate::uri::percent_decode(encoded)
+}
+
+/// A unique path in the temporary directory for an uploaded file.
+fn temp_path() -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    std::env::temp_dir().join(format!("stress-upload-{}-{id}", process::id()))
+}
+
+#[cfg(test)]
+mod multipart_tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Hands out at most `step` bytes per read so delimiters get split between reads.
+    struct Trickle<R> {
+        inner: R,
+        step: usize,
+    }
+
+    impl<R: Read> Read for Trickle<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.step);
+            self.inner.read(&mut buf[..len])
+        }
+    }
+
+    fn parse<R: Read>(reader: R, limits: &MultipartLimits) -> Result<Multipart, err> {
+        Parser {
+            reader: PartReader::new(reader),
+            delimiter: b"\r\n--XyZ".to_vec(),
+            limits,
+            total: 0,
+        }
+        .run()
+    }
+
+    fn status(result: Result<Multipart, err>) -> Option<u16> {
+        HttpError::status_of(&result.unwrap_err())
+    }
+
+    /// Uploaded files of this process left in the temporary directory.
+    fn leftovers() -> Vec<PathBuf> {
+        let prefix = format!("stress-upload-{}-", process::id());
+
+        fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+            })
+            .collect()
+    }
+
+    const FIELDS: &str = "preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\nhello world\r\n--XyZ\r\n\
+        content-disposition: form-data; name=\"tag\"\r\n\r\na\r\n--XyZ  \r\n\
+        Content-Disposition: form-data; name=\"tag\"\r\n\r\nb\r\n--XyZ--\r\nepilogue";
+
+    #[test]
+    fn parses_fields_across_reads() {
+        for step in [1, 2, 7, 8192] {
+            let reader = Trickle {
+                inner: Cursor::new(FIELDS),
+                step,
+            };
+            let form = parse(reader, &MultipartLimits::default()).unwrap();
+
+            assert_eq!(form.field("title"), Some("hello world"));
+            assert_eq!(form.fields["tag"], ["a", "b"]);
+            assert!(form.files.is_empty());
+        }
+
+        // without a preamble the body starts with the delimiter.
+        let raw = "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--XyZ--";
+        let form = parse(Cursor::new(raw), &MultipartLimits::default()).unwrap();
+        assert_eq!(form.field("a"), Some("1"));
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = |change: fn(&mut MultipartLimits)| {
+            let mut limits = MultipartLimits::default();
+            change(&mut limits);
+            limits
+        };
+        let fields = || Cursor::new(FIELDS);
+
+        let small_field = limits(|l| l.max_field_size = 4);
+        assert_eq!(status(parse(fields(), &small_field)), Some(413));
+        let few_parts = limits(|l| l.max_parts = 2);
+        assert_eq!(status(parse(fields(), &few_parts)), Some(413));
+        let small_total = limits(|l| l.max_total_size = 12);
+        assert_eq!(status(parse(fields(), &small_total)), Some(413));
+
+        let unclosed = "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1";
+        let unclosed = parse(Cursor::new(unclosed), &MultipartLimits::default());
+        assert_eq!(status(unclosed), Some(400));
+    }
+
+    #[test]
+    fn streams_files_and_cleans_up() {
+        let file = |name: &str, content: &str| {
+            format!(
+                "--XyZ\r\nContent-Disposition: form-data; name=\"{name}\"; \
+                filename*=UTF-8''caf%C3%A9.txt\r\nContent-Type: text/plain\r\n\r\n{content}\r\n"
+            )
+        };
+
+        let raw = format!("{}--XyZ--", file("doc", "file content"));
+        let form = parse(Cursor::new(raw), &MultipartLimits::default()).unwrap();
+        let part = form.file("doc").unwrap();
+        assert_eq!(part.filename, "café.txt");
+        assert_eq!(part.content_type, "text/plain");
+        assert_eq!(part.size, 12);
+        assert_eq!(fs::read_to_string(&part.path).unwrap(), "file content");
+
+        drop(form);
+        assert!(leftovers().is_empty());
+
+        // the first file is removed when the second one fails.
+        let raw = format!(
+            "{}{}--XyZ--",
+            file("a", "small"),
+            file("b", &"x".repeat(64))
+        );
+        let limits = MultipartLimits {
+            max_file_size: 16,
+            ..Default::default()
+        };
+        assert_eq!(status(parse(Cursor::new(raw), &limits)), Some(413));
+        assert!(leftovers().is_empty());
+    }
+}
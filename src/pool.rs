@@ -1,57 +1,121 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::error::Error;
-use std::io::Error as err;
-use std::net::TcpStream;
+use std::io::{self, BufRead, BufReader, Error as err, ErrorKind, Read};
+use std::mem;
+use std::net::{Shutdown, SocketAddr, TcpStream};
 use std::rc::Rc;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::sync::RwLock;
+use std::sync::{Mutex, PoisonError, RwLock};
 use std::thread;
+use std::time::{Duration, Instant};
 use thread::JoinHandle;
 
+use crate::config::ServerConfig;
 use crate::controller;
+use crate::error::{HttpError, Panicked};
+use crate::method::Method;
+use crate::request::ConnectionReader;
+use crate::shutdown::ShutdownHandle;
 use crate::Request;
 use crate::Response;
 use crate::Routes;
 
-pub type ErrorHandler = Arc<dyn Fn(err) + 'static + Send + Sync>;
+pub type ErrorHandler = Arc<dyn Fn(err, &ErrorContext) + 'static + Send + Sync>;
+
+/// What is known about the connection an error happened on.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorContext {
+    /// Address of the client, if the socket still has one.
+    pub peer: Option<SocketAddr>,
+    /// The request line as it was received, if it was read before the error.
+    pub request_line: Option<String>,
+}
+
+/// Called when a handler panics, the client was already answered with a `500` when possible.
+pub type PanicHandler = Arc<dyn Fn(&Panicked, &ErrorContext) + 'static + Send + Sync>;
+
+type Job = (TcpStream, Arc<RwLock<Routes>>);
 
-#[allow(unused)]
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    transmitter: Sender<(TcpStream, Arc<RwLock<Routes>>)>,
-    reciever: Arc<Mutex<Receiver<(TcpStream, Arc<RwLock<Routes>>)>>>,
+    size: usize,
+    transmitter: Option<Sender<Job>>,
+    /// Events for the supervisor, `None` when the pool isn't running.
+    events: Option<Sender<Event>>,
+    supervisor: Option<JoinHandle<()>>,
+    /// The connection each worker is serving, by worker id.
+    active: Arc<Mutex<HashMap<usize, TcpStream>>>,
     pub error_handler: ErrorHandler,
+    pub panic_handler: PanicHandler,
 }
 impl ThreadPool {
     pub fn new(worker_count: usize) -> Self {
-        let workers = Vec::with_capacity(worker_count);
-        let (transmitter, reciever) = mpsc::channel();
-        let recv = Arc::new(Mutex::new(reciever));
-
         ThreadPool {
-            transmitter,
-            reciever: recv,
-            workers,
-            error_handler: Arc::new(|e| eprintln!("{e}")),
+            size: worker_count,
+            transmitter: None,
+            events: None,
+            supervisor: None,
+            active: Arc::new(Mutex::new(HashMap::new())),
+            error_handler: Arc::new(|e, ctx| match (ctx.peer, &ctx.request_line) {
+                (Some(peer), Some(line)) => eprintln!("{peer} \"{line}\": {e}"),
+                (Some(peer), None) => eprintln!("{peer}: {e}"),
+                _ => eprintln!("{e}"),
+            }),
+            panic_handler: Arc::new(|panic, ctx| match (ctx.peer, &ctx.request_line) {
+                (Some(peer), Some(line)) => eprintln!("{peer} \"{line}\": {panic}"),
+                _ => eprintln!("{panic}"),
+            }),
         }
     }
 
+    /// Starts the workers and a supervisor that replaces the ones that die.
     pub fn init(
         &mut self,
         mime_map: Arc<HashMap<&'static str, &'static str>>,
         status_codes: Arc<HashMap<u16, String>>,
+        config: Arc<ServerConfig>,
+        shutdown: ShutdownHandle,
     ) {
-        for _ in 0..self.workers.capacity() {
-            self.workers.push(Worker::new(
-                self.reciever.clone(),
-                self.error_handler.clone(),
-                mime_map.clone(),
-                status_codes.clone(),
-            ));
-        }
+        let (transmitter, reciever) = mpsc::channel();
+        let (events, received) = mpsc::channel();
+        let context = WorkerContext {
+            recv: Arc::new(Mutex::new(reciever)),
+            on_error: self.error_handler.clone(),
+            on_panic: self.panic_handler.clone(),
+            mime_map,
+            status_codes,
+            config,
+            shutdown,
+            active: self.active.clone(),
+            events: events.clone(),
+        };
+
+        let mut workers = (0..self.size)
+            .map(|id| Worker::new(id, context.clone()))
+            .collect::<Vec<_>>();
+
+        self.transmitter = Some(transmitter);
+        self.events = Some(events);
+        self.supervisor = Some(thread::spawn(move || {
+            for event in received {
+                let Event::Died(id) = event else {
+                    break;
+                };
+                let worker = mem::replace(&mut workers[id], Worker::new(id, context.clone()));
+
+                if let Err(payload) = worker.thread.join() {
+                    (context.on_panic)(&Panicked::from_payload(payload), &ErrorContext::default());
+                }
+            }
+
+            // the queue is closed, so the workers stop once it's empty.
+            for worker in workers {
+                if let Err(payload) = worker.thread.join() {
+                    (context.on_panic)(&Panicked::from_payload(payload), &ErrorContext::default());
+                }
+            }
+        }));
     }
 
     pub fn execute(
@@ -59,52 +123,583 @@ impl ThreadPool {
         stream: TcpStream,
         handlers: Arc<RwLock<Routes>>,
     ) -> Result<(), Box<dyn Error>> {
-        self.transmitter.send((stream, handlers))?;
+        let transmitter = self
+            .transmitter
+            .as_ref()
+            .ok_or(err::new(ErrorKind::NotConnected, "The pool isn't running"))?;
+        transmitter.send((stream, handlers))?;
 
         Ok(())
     }
+
+    /// Stops the workers once the queued connections are served and joins them.
+    ///
+    /// Connections still open after `timeout` are shut down so their workers can stop,
+    /// a handler that never returns still blocks this.
+    pub fn shutdown(&mut self, timeout: Duration) {
+        let Some(supervisor) = self.supervisor.take() else {
+            return;
+        };
+
+        self.transmitter = None;
+        if let Some(events) = self.events.take() {
+            events.send(Event::Stop).unwrap_or_default();
+        }
+
+        let deadline = Instant::now() + timeout;
+        while !supervisor.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        if !supervisor.is_finished() {
+            let active = self.active.lock().unwrap_or_else(PoisonError::into_inner);
+            for stream in active.values() {
+                stream.shutdown(Shutdown::Both).unwrap_or_default();
+            }
+        }
+
+        supervisor.join().unwrap_or_default();
+    }
+}
+
+enum Event {
+    /// The worker with this id died from a panic.
+    Died(usize),
+    /// The pool is shutting down.
+    Stop,
+}
+
+/// Everything a worker needs, kept by the supervisor to start replacements.
+#[derive(Clone)]
+struct WorkerContext {
+    recv: Arc<Mutex<Receiver<Job>>>,
+    on_error: ErrorHandler,
+    on_panic: PanicHandler,
+    mime_map: Arc<HashMap<&'static str, &'static str>>,
+    status_codes: Arc<HashMap<u16, String>>,
+    config: Arc<ServerConfig>,
+    shutdown: ShutdownHandle,
+    active: Arc<Mutex<HashMap<usize, TcpStream>>>,
+    events: Sender<Event>,
 }
 
 struct Worker {
-    _thread: JoinHandle<()>,
+    thread: JoinHandle<()>,
 }
 
 impl Worker {
-    pub fn new(
-        recv: Arc<Mutex<Receiver<(TcpStream, Arc<RwLock<Routes>>)>>>,
-        error_handler: ErrorHandler,
-        mime_map: Arc<HashMap<&'static str, &'static str>>,
-        status_codes: Arc<HashMap<u16, String>>,
-    ) -> Self {
+    fn new(id: usize, context: WorkerContext) -> Self {
         let thread = thread::spawn(move || {
-            let on_error = error_handler;
+            let _notice = DeathNotice {
+                id,
+                events: context.events.clone(),
+            };
 
             #[cfg(debug_assertions)]
             dbg!("Thread is running!");
             loop {
-                let (stream, handlers) = recv.lock().unwrap().recv().unwrap();
-                let stream = Rc::new(RefCell::new(stream));
-                let routes = handlers.read().unwrap();
-
-                let res = Response::new(
-                    200,
-                    Arc::downgrade(&status_codes),
-                    stream.clone(),
-                    Arc::downgrade(&mime_map),
-                );
-
-                let req = match Request::build(stream.clone()) {
-                    Ok(data) => data,
-                    Err(error) => {
-                        on_error(error);
-                        continue;
-                    }
+                // a poisoned lock still holds a working receiver.
+                let job = context
+                    .recv
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .recv();
+                let Ok((stream, handlers)) = job else {
+                    return;
+                };
+
+                // kept so the pool can shut the connection down if it's still open at the deadline.
+                if let Ok(clone) = stream.try_clone() {
+                    context
+                        .active
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .insert(id, clone);
+                }
+                let _active = ActiveGuard {
+                    id,
+                    active: &context.active,
                 };
 
-                controller::handle_requests(req, res, routes);
+                handle_connection(stream, &handlers, &context);
+            }
+        });
+
+        Worker { thread }
+    }
+}
+
+/// Tells the supervisor when a worker thread dies from a panic.
+struct DeathNotice {
+    id: usize,
+    events: Sender<Event>,
+}
+
+impl Drop for DeathNotice {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.events.send(Event::Died(self.id)).unwrap_or_default();
+        }
+    }
+}
+
+/// Removes the connection of a worker from `active` once it's done with it, even after a panic,
+/// so the clone kept there doesn't hold the socket open.
+struct ActiveGuard<'a> {
+    id: usize,
+    active: &'a Mutex<HashMap<usize, TcpStream>>,
+}
+
+impl Drop for ActiveGuard<'_> {
+    fn drop(&mut self) {
+        self.active
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.id);
+    }
+}
+
+/// Serves requests on `stream` until either side closes the connection.
+///
+/// Requests are read through one buffered reader, so pipelined requests are answered in the order they were sent.
+///
+/// The connection is closed when the client asks for it, after `config.max_requests_per_connection` requests,
+/// when it stays idle for `config.keep_alive_timeout` or after a response without proper framing.
+///
+/// The head of every request must arrive within `config.header_read_timeout` and the body must keep
+/// up with `config.min_transfer_rate`, slower clients are answered with `408` when possible.
+fn handle_connection(stream: TcpStream, routes: &RwLock<Routes>, worker: &WorkerContext) {
+    let WorkerContext {
+        on_error,
+        on_panic,
+        mime_map,
+        status_codes,
+        config,
+        shutdown,
+        ..
+    } = worker;
+    let mut context = ErrorContext {
+        peer: stream.peer_addr().ok(),
+        request_line: None,
+    };
+    let reader = match stream.try_clone() {
+        Ok(clone) => Rc::new(RefCell::new(BufReader::new(TimedStream::new(clone)))),
+        Err(error) => return on_error(error, &context),
+    };
+
+    if let Err(error) = stream.set_write_timeout(Some(config.write_timeout)) {
+        return on_error(error, &context);
+    }
+    let stream = Rc::new(RefCell::new(stream));
+
+    for served in 1..=config.max_requests_per_connection.max(1) {
+        if served > 1 && !wait_for_request(&reader, config, shutdown) {
+            return;
+        }
+        reader
+            .borrow_mut()
+            .get_mut()
+            .head(Instant::now() + config.header_read_timeout);
+
+        let mut res = Response::new(
+            200,
+            Arc::downgrade(status_codes),
+            stream.clone(),
+            Arc::downgrade(mime_map),
+        );
+
+        let mut head = Vec::new();
+        let parsed = Request::read_head(&reader, config, &mut head)
+            .and_then(|_| Request::parse(head.clone(), stream.clone(), reader.clone(), config));
+        context.request_line = head.first().cloned();
+
+        let mut req = match parsed {
+            Ok(data) => data,
+            Err(error) => {
+                // a client closing an idle connection isn't an error.
+                if is_disconnect(&error) && head.is_empty() {
+                    return;
+                }
+
+                if let Some(status) = HttpError::status_of(&error) {
+                    reject(&mut res, status).unwrap_or_default();
+                }
+                return on_error(error, &context);
+            }
+        };
+
+        if !req.keep_alive()
+            || served == config.max_requests_per_connection
+            || shutdown.is_shutdown()
+        {
+            res.close_connection();
+        } else if req.http_version == "HTTP/1.0" {
+            // 1.0 connections close by default, unless told otherwise.
+            res.set_header("connection", "keep-alive")
+                .unwrap_or_default();
+        }
+        res.chunked_allowed = req.http_version != "HTTP/1.0";
+        res.head = req.method == Method::Head;
+        reader
+            .borrow_mut()
+            .get_mut()
+            .body(config.body_read_timeout, config.min_transfer_rate);
+
+        let routes = routes.read().unwrap_or_else(PoisonError::into_inner);
+        controller::handle_requests(&mut req, &mut res, &routes);
+
+        if let Some(panic) = req
+            .error
+            .as_deref()
+            .and_then(|e| e.downcast_ref::<Panicked>())
+        {
+            // the handler may have left the response half written.
+            return on_panic(panic, &context);
+        }
+
+        if !res.keep_alive() || !req.drain_body() {
+            return;
+        }
+    }
+}
+
+/// How often an idle connection checks if the server is shutting down.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+/// Waits for the next request on a kept-alive connection.
+///
+/// ## Returns:
+/// `false` if the connection was closed, stayed idle for `config.keep_alive_timeout`
+/// or the server is shutting down.
+fn wait_for_request(
+    reader: &ConnectionReader,
+    config: &ServerConfig,
+    shutdown: &ShutdownHandle,
+) -> bool {
+    let mut reader = reader.borrow_mut();
+    let deadline = Instant::now() + config.keep_alive_timeout;
+
+    loop {
+        // a pipelined request was already read.
+        if !reader.buffer().is_empty() {
+            return true;
+        }
+
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || shutdown.is_shutdown() {
+            return false;
+        }
+
+        reader.get_mut().idle(left.min(SHUTDOWN_POLL));
+        match reader.fill_buf() {
+            Ok(buf) => return !buf.is_empty(),
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => return false,
+        }
+    }
+}
+
+/// The reading end of a connection, with the read timeouts of the part being read.
+#[derive(Debug)]
+pub struct TimedStream {
+    stream: TcpStream,
+    /// Limit for a single read.
+    timeout: Option<Duration>,
+    /// Limit for all the reads until the phase changes.
+    deadline: Option<Instant>,
+    /// Minimum bytes per second, `0` disables it.
+    min_rate: u64,
+    read: u64,
+    /// Time spent waiting in reads.
+    waited: Duration,
+}
+
+impl TimedStream {
+    pub(crate) fn new(stream: TcpStream) -> Self {
+        TimedStream {
+            stream,
+            timeout: None,
+            deadline: None,
+            min_rate: 0,
+            read: 0,
+            waited: Duration::ZERO,
+        }
+    }
+
+    /// Waiting for the next request on a kept-alive connection.
+    fn idle(&mut self, timeout: Duration) {
+        self.set_phase(Some(timeout), None, 0);
+    }
+
+    /// Reading the request line and headers.
+    fn head(&mut self, deadline: Instant) {
+        self.set_phase(None, Some(deadline), 0);
+    }
+
+    /// Reading the request body.
+    fn body(&mut self, timeout: Duration, min_rate: u64) {
+        self.set_phase(Some(timeout), None, min_rate);
+    }
+
+    fn set_phase(&mut self, timeout: Option<Duration>, deadline: Option<Instant>, min_rate: u64) {
+        self.timeout = timeout;
+        self.deadline = deadline;
+        self.min_rate = min_rate;
+        self.read = 0;
+        self.waited = Duration::ZERO;
+    }
+}
+
+impl Read for TimedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut timeout = self.timeout;
+        if let Some(deadline) = self.deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(err::new(ErrorKind::TimedOut, "Read deadline passed"));
             }
+            timeout = Some(timeout.map_or(left, |timeout| timeout.min(left)));
+        }
+        self.stream.set_read_timeout(timeout)?;
+
+        let start = Instant::now();
+        let read = self.stream.read(buf)?;
+        self.waited += start.elapsed();
+        self.read += read as u64;
+
+        // the first second is a grace period so slow starts aren't punished.
+        let waited = self.waited.as_secs_f64();
+        if self.min_rate > 0 && waited > 1.0 && (self.read as f64) < self.min_rate as f64 * waited {
+            return Err(err::new(ErrorKind::TimedOut, "Transfer rate is too low"));
+        }
+
+        Ok(read)
+    }
+}
+
+fn is_disconnect(error: &err) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::UnexpectedEof
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+    )
+}
+
+/// Answers a request that couldn't be parsed with `status` and closes the connection.
+fn reject(res: &mut Response, status: u16) -> Result<(), Box<dyn Error>> {
+    res.set_status(status)?;
+    res.set_header("connection", "close")?;
+    let message = res.status_message().to_string();
+    res.send(&message)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod pool_tests {
+    use super::*;
+    use std::io::Write;
+
+    use crate::testing::{connect, Connection};
+
+    /// A worker that runs connections inline, with `config`.
+    fn worker(config: ServerConfig) -> WorkerContext {
+        WorkerContext {
+            recv: Arc::new(Mutex::new(mpsc::channel().1)),
+            on_error: Arc::new(|_, _| {}),
+            on_panic: Arc::new(|_, _| {}),
+            mime_map: Arc::new(HashMap::new()),
+            status_codes: Arc::new(crate::import_status_messages()),
+            config: Arc::new(config),
+            shutdown: ShutdownHandle::default(),
+            active: Arc::default(),
+            events: mpsc::channel().0,
+        }
+    }
+
+    /// Adds a `GET` route to `routes`.
+    fn get(routes: &mut Routes, path: &'static str, handler: crate::RouteHandler) {
+        routes.methods.entry(Method::Get).or_default().insert(
+            path,
+            crate::Route {
+                handler,
+                method: "GET",
+                path,
+            },
+        );
+    }
+
+    #[test]
+    fn reads_pipelined_requests_in_order() {
+        let conn = Connection::open(
+            "POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+             POST /b HTTP/1.1\r\nContent-Length: 4\r\n\r\nskip\
+             GET /c HTTP/1.1\r\n\r\n",
+        );
+
+        let mut a = conn.request();
+        assert_eq!(a.path, "/a");
+        assert_eq!(a.body_bytes().unwrap(), b"hello");
+        assert!(a.drain_body());
+
+        // nothing reads the body of `/b`, it's drained so `/c` starts at the right place.
+        let mut b = conn.request();
+        assert_eq!(b.path, "/b");
+        assert!(b.drain_body());
+
+        let mut c = conn.request();
+        assert_eq!(c.path, "/c");
+        assert_eq!(c.body_bytes().unwrap(), b"");
+    }
+
+    #[test]
+    fn only_drains_small_bodies() {
+        // the body is never sent, nothing is read when the length is over the limit.
+        let conn = Connection::open("POST / HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n");
+        assert!(!conn.request().drain_body());
+
+        let chunk = "x".repeat(70_000);
+        let conn = Connection::open(&format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{chunk}\r\n0\r\n\r\n",
+            chunk.len()
+        ));
+        assert!(!conn.request().drain_body());
+
+        let conn = Connection::open(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+        );
+        assert!(conn.request().drain_body());
+    }
+
+    #[test]
+    fn closes_after_max_requests_per_connection() {
+        let mut routes = Routes::default();
+        get(
+            &mut routes,
+            "/:n",
+            Box::new(|req, res| {
+                let n = req.param("n").unwrap_or_default().to_string();
+                res.send(n)?;
+                Ok(true)
+            }),
+        );
+        let worker = worker(ServerConfig {
+            max_requests_per_connection: 2,
+            ..Default::default()
+        });
+
+        let (mut client, server) = connect("GET /1 HTTP/1.1\r\n\r\nGET /2 HTTP/1.1\r\n\r\n");
+        handle_connection(server, &RwLock::new(routes), &worker);
+
+        let mut out = String::new();
+        client.read_to_string(&mut out).unwrap();
+        let responses = out.split("HTTP/1.1 200 OK").skip(1).collect::<Vec<_>>();
+
+        // both are answered in order, the first keeps the connection open and the last closes it.
+        assert_eq!(responses.len(), 2);
+        assert!(responses[0].ends_with("\r\n\r\n1") && !responses[0].contains("connection: close"));
+        assert!(responses[1].ends_with("\r\n\r\n2") && responses[1].contains("connection: close"));
+    }
+
+    #[test]
+    fn answers_panics_and_closes() {
+        let mut routes = Routes::default();
+        get(
+            &mut routes,
+            "/panic",
+            Box::new(|_, _| panic!("handler failed")),
+        );
+        get(
+            &mut routes,
+            "/stream",
+            Box::new(|_, res| {
+                let mut writer = res.writer()?;
+                writer.write_all(b"hello")?;
+                panic!("writer failed")
+            }),
+        );
+        let routes = RwLock::new(routes);
+
+        let panics = Arc::new(Mutex::new(Vec::new()));
+        let mut worker = worker(ServerConfig::default());
+        let seen = panics.clone();
+        worker.on_panic = Arc::new(move |panic, ctx| {
+            let line = ctx.request_line.clone().unwrap_or_default();
+            seen.lock()
+                .unwrap()
+                .push(format!("{line}: {}", panic.message));
         });
 
-        Worker { _thread: thread }
+        // the request after the panic isn't served, the connection is closed.
+        let (mut client, server) =
+            connect("GET /panic HTTP/1.1\r\n\r\nGET /panic HTTP/1.1\r\n\r\n");
+        handle_connection(server, &routes, &worker);
+        let mut out = String::new();
+        client.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 500 "));
+        assert!(out.contains("connection: close"));
+        assert_eq!(out.matches("HTTP/1.1").count(), 1);
+
+        // the headers are already sent, the body is left without its last chunk.
+        let (mut client, server) = connect("GET /stream HTTP/1.1\r\n\r\n");
+        handle_connection(server, &routes, &worker);
+        let mut out = String::new();
+        client.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 "));
+        assert!(out.ends_with("\r\n\r\n5\r\nhello\r\n"));
+
+        assert_eq!(
+            *panics.lock().unwrap(),
+            [
+                "GET /panic HTTP/1.1: handler failed",
+                "GET /stream HTTP/1.1: writer failed"
+            ]
+        );
+    }
+
+    #[test]
+    fn replaces_dead_workers() {
+        let mut routes = Routes::default();
+        get(
+            &mut routes,
+            "/",
+            Box::new(|_, res| {
+                res.send("ok")?;
+                Ok(true)
+            }),
+        );
+        let routes = Arc::new(RwLock::new(routes));
+
+        // a panic outside the handlers isn't caught, it kills the only worker.
+        let panics = Arc::new(Mutex::new(Vec::new()));
+        let seen = panics.clone();
+        let mut pool = ThreadPool::new(1);
+        pool.error_handler = Arc::new(|_, _| panic!("error handler failed"));
+        pool.panic_handler =
+            Arc::new(move |panic, _| seen.lock().unwrap().push(panic.message.clone()));
+        pool.init(
+            Arc::new(HashMap::new()),
+            Arc::new(crate::import_status_messages()),
+            Arc::new(ServerConfig::default()),
+            ShutdownHandle::default(),
+        );
+
+        let (mut client, server) = connect("nonsense\r\n\r\n");
+        pool.execute(server, routes.clone()).unwrap();
+        let mut out = String::new();
+        client.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 400 "));
+
+        let (mut client, server) = connect("GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        pool.execute(server, routes).unwrap();
+        let mut out = String::new();
+        client.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 ") && out.ends_with("\r\n\r\nok"));
+
+        pool.shutdown(Duration::from_secs(1));
+        assert_eq!(*panics.lock().unwrap(), ["error handler failed"]);
     }
 }
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use crate::uri::hex_pair;
+
+/// Decoded query parameters, every key can hold multiple values in the order they were sent.
+pub type QueryMap = HashMap<String, Vec<String>>;
+
+/// Parses a query string like `a=1&b=2&a=3` into a `QueryMap`.
+///
+/// Keys and values are percent-decoded and `+` is treated as a space.
+/// Keys without a value (`?flag`) get an empty string.
+pub fn parse(query: &str) -> QueryMap {
+    let mut map = QueryMap::new();
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, val) = pair.split_once('=').unwrap_or((pair, ""));
+
+        map.entry(decode_component(key))
+            .or_default()
+            .push(decode_component(val));
+    }
+
+    map
+}
+
+/// Decodes a single query component, `+` is decoded as a space.
+///
+/// Invalid escapes are kept as they are and invalid UTF-8 is replaced.
+pub fn decode_component(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match bytes
+                .get(i + 1..i + 3)
+                .and_then(|pair| hex_pair(pair[0], pair[1]))
+            {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                None => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod query_tests {
+    use super::*;
+
+    #[test]
+    fn parses_multi_value_queries() {
+        let map = parse("q=caf%C3%A9+au+lait&tag=a&tag=b&flag&bad=%zz");
+
+        assert_eq!(map["q"], ["café au lait"]);
+        assert_eq!(map["tag"], ["a", "b"]);
+        assert_eq!(map["flag"], [""]);
+        assert_eq!(map["bad"], ["%zz"]);
+    }
+}
@@ -1,82 +1,810 @@
-use std::cell::RefCell;
+use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
 use std::error::Error;
 use std::io::{self, prelude::*, BufReader, Error as err};
 use std::net::{IpAddr, TcpStream};
+use std::ops::DerefMut;
 use std::rc::Rc;
+use std::str::{self, FromStr};
 
-use super::extract_option;
+use crate::config::ServerConfig;
+use crate::cookie;
+use crate::error::HttpError;
+use crate::header::{self, HeaderMap};
+use crate::json::{self, Value};
+use crate::method::Method;
+use crate::pool::TimedStream;
+use crate::query::{self, QueryMap};
+use crate::router::Params;
+use crate::uri;
+
+/// A buffered reader over a connection, shared by all the requests read from it.
+pub type ConnectionReader = Rc<RefCell<BufReader<TimedStream>>>;
 
 #[derive(Debug)]
 pub struct Request {
-    pub method: String,
+    pub method: Method,
+    /// The decoded and normalized request path without the query string, used for routing.
     pub path: String,
+    /// The path exactly as it was sent, without the query string.
+    pub raw_path: String,
     pub host: String,
     pub http_version: String,
-    headers: HashMap<String, String>,
+    headers: HeaderMap,
     pub ip: IpAddr,
     pub user_agent: String,
-    pub body: Option<()>,
+    /// The reader shared by every request on the connection, it's positioned at the unread part of the body.
+    reader: ConnectionReader,
+    framing: Framing,
+    /// The body once it's buffered by `body_bytes`.
+    body: Option<Vec<u8>>,
+    /// Limit of the buffered body, `config.max_body_size`.
+    max_body_size: usize,
     pub socket: Rc<RefCell<TcpStream>>,
     pub error: Option<Box<dyn Error>>,
+    /// Values captured by the route being run, see `param`.
+    pub(crate) params: Params,
+    raw_query: String,
+    query: QueryMap,
 }
 
 impl Request {
-    /// Builds a `Request` object from a connection stream.
-    pub fn build(stream: Rc<RefCell<TcpStream>>) -> Result<Self, err> {
-        let reader = BufReader::new(stream.borrow().try_clone()?);
-        let ip = stream.borrow().peer_addr()?.ip();
+    /// Reads the request line and the headers into `head`, one line per item.
+    ///
+    /// Lines are pushed as they're read so whatever was read is still in `head` if this fails.
+    /// Reads are bounded by the limits in `config`, nothing past them is buffered.
+    ///
+    /// ## Fails:
+    /// With an `HttpError` carrying the status to answer with,
+    /// or an `UnexpectedEof` if the connection was closed before a request was sent.
+    pub(crate) fn read_head(
+        reader: &ConnectionReader,
+        config: &ServerConfig,
+        head: &mut Vec<String>,
+    ) -> Result<(), err> {
+        let mut reader = reader.borrow_mut();
+        let mut size = 0;
 
-        let data: Vec<String> = reader
-            .lines()
-            .map(|result| result.unwrap_or("".to_string()))
-            .take_while(|line| !line.is_empty())
-            .collect();
+        loop {
+            let (limit, error) = if head.is_empty() {
+                (
+                    config.max_request_line,
+                    HttpError::new(414, "Request line is too long"),
+                )
+            } else {
+                (
+                    config.max_header_size.saturating_sub(size),
+                    HttpError::new(431, "Request headers are too large"),
+                )
+            };
 
-        let mut metadata = if let Some(first) = data.first() {
-            first.split(" ")
-        } else {
-            return Err(err::new(io::ErrorKind::InvalidData, "Invalid Request"));
-        };
+            // the line may end with CRLF on top of the limit.
+            let bound = limit as u64 + 2;
+            let mut line = Vec::new();
+            let read = match (&mut *reader).take(bound).read_until(b'\n', &mut line) {
+                // an idle connection is just closed, a request that started gets a `408`.
+                Err(error) if head.is_empty() && line.is_empty() => return Err(error),
+                result => result.map_err(timed_out)?,
+            };
+
+            if !line.ends_with(b"\n") {
+                if read as u64 == bound {
+                    if head.is_empty() {
+                        head.push(String::from_utf8_lossy(&line).into_owned());
+                    }
+                    return Err(error.into());
+                }
+
+                return Err(err::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed before the request was received",
+                ));
+            }
 
-        let method = extract_option(metadata.next())?.to_string();
-        let path = extract_option(metadata.next())?.to_string();
-        let http_version = extract_option(metadata.next())?.to_string();
-        let mut headers = HashMap::new();
+            let line = String::from_utf8(line)
+                .map_err(|_| HttpError::new(400, "Request head is not valid UTF-8"))?;
+            let line = line.trim_end_matches(['\r', '\n']);
 
-        for line in &data {
-            if *line == data[0] {
+            if line.is_empty() && head.is_empty() {
+                // empty lines before the request line are ignored.
                 continue;
             }
+            if line.is_empty() {
+                return Ok(());
+            }
+            if line.len() > limit {
+                if head.is_empty() {
+                    head.push(line.to_string());
+                }
+                return Err(error.into());
+            }
+
+            if !head.is_empty() {
+                size += line.len();
+            }
+            head.push(line.to_string());
 
-            let (key, val) = extract_option(line.split_once(":"))?;
-            headers.insert(key.to_string(), val.trim().to_string());
+            if head.len() - 1 > config.max_header_count {
+                return Err(HttpError::new(431, "Too many request headers").into());
+            }
         }
+    }
 
-        if http_version != String::from("HTTP/1.1") {
-            return Err(err::new(
-                io::ErrorKind::InvalidData,
-                format!("Unsupported HTTP version {http_version}"),
-            ));
+    /// Builds the request from the lines read by `read_head`.
+    ///
+    /// Only the head is parsed here, the body is left on the connection until it's requested
+    /// through `body_bytes` or `body_reader`.
+    ///
+    /// `reader` must be the same for every request on the connection so bytes that were
+    /// buffered past this request (pipelined requests) are not lost.
+    ///
+    /// ## Fails:
+    /// With an `HttpError` carrying the status to answer with (see `HttpError::status_of`).
+    pub(crate) fn parse(
+        head: Vec<String>,
+        stream: Rc<RefCell<TcpStream>>,
+        reader: ConnectionReader,
+        config: &ServerConfig,
+    ) -> Result<Self, err> {
+        let ip = stream.borrow().peer_addr()?.ip();
+        let malformed = || HttpError::new(400, "Malformed request line");
+
+        let request_line = head.first().ok_or_else(malformed)?;
+        let mut metadata = request_line.split(' ');
+        let (method, target, http_version) = match (
+            metadata.next(),
+            metadata.next(),
+            metadata.next(),
+            metadata.next(),
+        ) {
+            (Some(method), Some(target), Some(version), None) => (method, target, version),
+            _ => return Err(malformed().into()),
+        };
+
+        if !header::is_valid_name(method) || target.is_empty() {
+            return Err(malformed().into());
+        }
+
+        let method = Method::from(method);
+        let http_version = parse_version(http_version)?;
+        let (raw_path, raw_query) = target.split_once('?').unwrap_or((target, ""));
+        let path = uri::normalize_path(raw_path)?;
+        let raw_path = raw_path.to_string();
+        let raw_query = raw_query.to_string();
+        let mut headers = HeaderMap::new();
+
+        for line in head.iter().skip(1) {
+            // obsolete line folding isn't supported.
+            if line.starts_with([' ', '\t']) {
+                return Err(HttpError::new(400, "Folded headers are not supported").into());
+            }
+
+            let (key, val) = line
+                .split_once(':')
+                .ok_or(HttpError::new(400, "Malformed header"))?;
+            headers
+                .append(key, val.trim())
+                .map_err(|_| HttpError::new(400, "Invalid header"))?;
         }
 
+        let framing = body_framing(&headers, config.max_stream_size)?;
+
         Ok(Request {
             ip,
             method,
             path,
+            raw_path,
             http_version,
             host: headers.get("Host").unwrap_or(&"".to_string()).clone(),
             user_agent: headers.get("User-Agent").unwrap_or(&"".to_string()).clone(),
             headers,
+            reader,
+            framing,
             body: None,
+            max_body_size: config.max_body_size,
             socket: stream,
             error: None,
+            params: Vec::new(),
+            query: query::parse(&raw_query),
+            raw_query,
         })
     }
 
-    /// Returns the request header.
+    /// Whether the client allows the connection to be reused after this request.
+    ///
+    /// HTTP/1.1 connections are persistent unless `Connection: close` is sent.
+    pub fn keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.headers
+                .get_all("Connection")
+                .iter()
+                .flat_map(|val| val.split(','))
+                .any(|val| val.trim().eq_ignore_ascii_case(token))
+        };
+
+        if self.http_version == "HTTP/1.1" {
+            !has_token("close")
+        } else {
+            has_token("keep-alive")
+        }
+    }
+
+    /// Reads and discards what's left of the body so the next request can be read.
+    ///
+    /// At most `MAX_DRAIN` bytes are read, it's cheaper to close the connection than to
+    /// wait for the rest of a big body nobody reads.
+    ///
+    /// ## Returns:
+    /// `false` if the body couldn't be read or more is left, the connection can't be reused then.
+    pub(crate) fn drain_body(&mut self) -> bool {
+        if self.body_failed() || matches!(self.framing, Framing::Length(left) if left > MAX_DRAIN) {
+            return false;
+        }
+
+        let mut rest = self.body_reader().take(MAX_DRAIN + 1);
+        matches!(io::copy(&mut rest, &mut io::sink()), Ok(read) if read <= MAX_DRAIN)
+    }
+
+    /// Whether reading the body failed, the end of the body is unknown after that
+    /// so the rest of the connection can't be trusted.
+    pub(crate) fn body_failed(&self) -> bool {
+        matches!(self.framing, Framing::Failed(_))
+    }
+
+    /// Returns the first value of a request header, the name is case-insensitive.
     pub fn get_header(&self, val: &str) -> Option<&String> {
         self.headers.get(val)
     }
+
+    /// Returns every value of a repeated request header.
+    pub fn get_all_headers(&self, val: &str) -> Vec<&String> {
+        self.headers.get_all(val)
+    }
+
+    /// All the request headers in the order they were sent.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Parses the `Cookie` header into a map of names and values.
+    pub fn cookies(&self) -> HashMap<String, String> {
+        self.get_header("Cookie")
+            .map(|val| cookie::parse(val))
+            .unwrap_or_default()
+    }
+
+    /// Returns the value captured by the `:name` or `*name` segment of the matched route.
+    ///
+    /// Only set while route handlers run, middlewares and finals don't see route parameters.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, val)| val.as_str())
+    }
+
+    /// Parses the route parameter `name` as `T`, like `req.param_as::<u64>("id")`.
+    ///
+    /// ## Fails:
+    /// With a `400` if the parameter is missing or can't be parsed.
+    pub fn param_as<T: FromStr>(&self, name: &str) -> Result<T, err> {
+        let value = self.param(name).ok_or(HttpError::new(
+            400,
+            &format!("Missing route parameter {name}"),
+        ))?;
+
+        value
+            .parse()
+            .map_err(|_| HttpError::new(400, &format!("Invalid route parameter {name}")).into())
+    }
+
+    /// Returns the first value of the query parameter `name`.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.get(name)?.first().map(|val| val.as_str())
+    }
+
+    /// Returns every value of the query parameter `name` in the order they were sent.
+    pub fn query_all(&self, name: &str) -> Vec<&str> {
+        self.query
+            .get(name)
+            .map(|vals| vals.iter().map(|val| val.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    /// The query string as it was sent, without the leading `?`.
+    pub fn raw_query(&self) -> &str {
+        &self.raw_query
+    }
+
+    /// Returns the first value of a trailer header sent after a chunked body, the name is case-insensitive.
+    ///
+    /// Trailers are only available once the whole body has been read.
+    pub fn get_trailer(&self, val: &str) -> Option<&String> {
+        match &self.framing {
+            Framing::Chunked(decoder) => decoder.trailers.get(val),
+            _ => None,
+        }
+    }
+
+    /// Returns every value of a repeated trailer header.
+    pub fn get_all_trailers(&self, val: &str) -> Vec<&String> {
+        match &self.framing {
+            Framing::Chunked(decoder) => decoder.trailers.get_all(val),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Reads the whole body into memory, empty if the request had none.
+    ///
+    /// The body is read using the `Content-Length` header or decoded if it's sent with
+    /// `Transfer-Encoding: chunked`, either way it's limited by `config.max_body_size`.
+    ///
+    /// Bytes that were already consumed through `body_reader` are not included.
+    pub fn body_bytes(&mut self) -> Result<&[u8], err> {
+        if self.body.is_none() {
+            let limit = self.max_body_size as u64;
+            if matches!(self.framing, Framing::Length(length) if length > limit) {
+                return Err(self.fail(HttpError::new(413, "Request body is too large").into()));
+            }
+
+            let mut body = Vec::new();
+            self.body_reader().take(limit + 1).read_to_end(&mut body)?;
+            if body.len() as u64 > limit {
+                return Err(self.fail(HttpError::new(413, "Request body is too large").into()));
+            }
+            self.body = Some(body);
+        }
+
+        Ok(self.body.as_deref().unwrap_or_default())
+    }
+
+    /// Marks the body as failed with `error`, the rest of it is never read.
+    fn fail(&mut self, error: err) -> err {
+        self.framing = Framing::Failed(Failure::new(&error));
+        error
+    }
+
+    /// The request body as text.
+    ///
+    /// ## Fails:
+    /// if reading fails or the body isn't valid UTF-8.
+    pub fn body_text(&mut self) -> Result<&str, err> {
+        str::from_utf8(self.body_bytes()?)
+            .map_err(|_| HttpError::new(400, "Request body is not valid UTF-8").into())
+    }
+
+    /// Parses an `application/x-www-form-urlencoded` body, decoded the same way as the query string.
+    ///
+    /// ## Fails:
+    /// With a `415` if the request has a different content type,
+    /// or if reading the body fails (it's limited by `config.max_body_size`).
+    pub fn form(&mut self) -> Result<QueryMap, err> {
+        if !self.is_content_type("application/x-www-form-urlencoded") {
+            return Err(HttpError::new(415, "Expected a urlencoded form").into());
+        }
+
+        Ok(query::parse(self.body_text()?))
+    }
+
+    /// Parses a JSON body.
+    ///
+    /// ## Fails:
+    /// With a `415` if the content type isn't `application/json` (or a `+json` type),
+    /// a `400` if the body isn't valid JSON or if reading the body fails.
+    pub fn json(&mut self) -> Result<Value, err> {
+        let is_json = self
+            .get_header("Content-Type")
+            .and_then(|val| val.split(';').next())
+            .map(|val| val.trim().to_ascii_lowercase())
+            .is_some_and(|val| val == "application/json" || val.ends_with("+json"));
+
+        if !is_json {
+            return Err(HttpError::new(415, "Expected a JSON body").into());
+        }
+
+        // the body is already limited by `config.max_body_size`.
+        let parser = json::Parser {
+            max_size: usize::MAX,
+            ..Default::default()
+        };
+
+        parser
+            .parse(self.body_text()?)
+            .map_err(|e| HttpError::new(400, &format!("Invalid JSON: {e}")).into())
+    }
+
+    /// Checks the media type of the `Content-Type` header, parameters like `charset` are ignored.
+    pub fn is_content_type(&self, media_type: &str) -> bool {
+        self.get_header("Content-Type")
+            .and_then(|val| val.split(';').next())
+            .is_some_and(|val| val.trim().eq_ignore_ascii_case(media_type))
+    }
+
+    /// Returns a reader over the body that pulls directly from the socket.
+    ///
+    /// Use this instead of `body_bytes` to handle large uploads without buffering them,
+    /// the body is limited by `config.max_stream_size` instead of `config.max_body_size`.
+    pub fn body_reader(&mut self) -> BodyReader<'_> {
+        BodyReader {
+            reader: self.reader.borrow_mut(),
+            framing: &mut self.framing,
+        }
+    }
+}
+
+/// Checks the version of the request line, only HTTP/1.0 and HTTP/1.1 are supported.
+///
+/// ## Fails:
+/// With a `505` for other versions and a `400` if it's not an HTTP version at all.
+fn parse_version(version: &str) -> Result<String, err> {
+    let number = version
+        .strip_prefix("HTTP/")
+        .filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        .ok_or(HttpError::new(400, "Malformed HTTP version"))?;
+
+    match number {
+        "1.0" | "1.1" => Ok(version.to_string()),
+        _ => Err(HttpError::new(505, &format!("Unsupported HTTP version {version}")).into()),
+    }
+}
+
+/// Decides how the body is framed from the `Transfer-Encoding` or `Content-Length` headers.
+///
+/// Requests with both headers or with conflicting lengths are rejected since they can be used for smuggling.
+fn body_framing(headers: &HeaderMap, limit: u64) -> Result<Framing, err> {
+    let encodings = headers.get_all("Transfer-Encoding");
+    let lengths = headers.get_all("Content-Length");
+
+    if !encodings.is_empty() && !lengths.is_empty() {
+        return Err(
+            HttpError::new(400, "Both Transfer-Encoding and Content-Length are set").into(),
+        );
+    }
+    if lengths.iter().any(|val| *val != lengths[0]) {
+        return Err(HttpError::new(400, "Conflicting Content-Length headers").into());
+    }
+
+    if !encodings.is_empty() {
+        let encoding = encodings
+            .iter()
+            .map(|val| val.as_str())
+            .collect::<Vec<&str>>()
+            .join(",");
+        let last = encoding.rsplit(',').next().unwrap_or_default().trim();
+
+        if !last.eq_ignore_ascii_case("chunked") {
+            return Err(HttpError::new(400, "Chunked must be the final transfer coding").into());
+        }
+        if encoding.contains(',') {
+            return Err(HttpError::new(501, "Unsupported transfer coding").into());
+        }
+
+        return Ok(Framing::Chunked(ChunkedDecoder::new(limit)));
+    }
+
+    let length = match lengths.first() {
+        Some(val) => val
+            .parse::<u64>()
+            .map_err(|_| HttpError::new(400, "Invalid Content-Length"))?,
+        None => return Ok(Framing::Empty),
+    };
+
+    if length > limit {
+        return Err(HttpError::new(413, "Request body is too large").into());
+    }
+
+    Ok(Framing::Length(length))
+}
+
+/// How the unread part of the body is delimited on the connection.
+#[derive(Debug)]
+enum Framing {
+    Empty,
+    /// bytes left to read.
+    Length(u64),
+    Chunked(ChunkedDecoder),
+    /// Reading the body failed, every later read fails the same way.
+    Failed(Failure),
+}
+
+/// The first error of a body, kept so it can be returned again.
+#[derive(Debug, Clone)]
+struct Failure {
+    kind: io::ErrorKind,
+    status: Option<u16>,
+    message: String,
+}
+
+impl Failure {
+    fn new(error: &err) -> Self {
+        let http = error.get_ref().and_then(|e| e.downcast_ref::<HttpError>());
+
+        Failure {
+            kind: error.kind(),
+            status: http.map(|e| e.status),
+            message: http.map_or(error.to_string(), |e| e.message.clone()),
+        }
+    }
+
+    fn error(&self) -> err {
+        match self.status {
+            Some(status) => HttpError::new(status, &self.message).into(),
+            None => err::new(self.kind, self.message.as_str()),
+        }
+    }
+}
+
+/// Reads the request body from the connection, see `Request::body_reader`.
+///
+/// Reading stops at the end of the body so the bytes after it are left on the connection.
+pub struct BodyReader<'a, R = RefMut<'a, BufReader<TimedStream>>> {
+    reader: R,
+    framing: &'a mut Framing,
+}
+
+impl<R> Read for BodyReader<'_, R>
+where
+    R: DerefMut,
+    R::Target: BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let reader = &mut *self.reader;
+
+        let read = match self.framing {
+            Framing::Empty => Ok(0),
+            Framing::Length(remaining) => {
+                if *remaining == 0 || buf.is_empty() {
+                    return Ok(0);
+                }
+
+                let max = buf
+                    .len()
+                    .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                match reader.read(&mut buf[..max]) {
+                    Ok(0) => Err(err::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Connection closed before the body was received",
+                    )),
+                    Ok(read) => {
+                        *remaining -= read as u64;
+                        Ok(read)
+                    }
+                    Err(error) => Err(error),
+                }
+            }
+            Framing::Chunked(decoder) => decoder.read_from(reader, buf),
+            Framing::Failed(failure) => return Err(failure.error()),
+        };
+
+        read.map_err(timed_out).inspect_err(|error| {
+            if error.kind() != io::ErrorKind::Interrupted {
+                *self.framing = Framing::Failed(Failure::new(error));
+            }
+        })
+    }
+}
+
+/// Turns a read timeout into a `408` so it's answered like the other request errors.
+fn timed_out(error: err) -> err {
+    match error.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            HttpError::new(408, "Timed out reading the request").into()
+        }
+        _ => error,
+    }
+}
+
+/// Most bytes of an unread body `Request::drain_body` reads to keep the connection open.
+const MAX_DRAIN: u64 = 64 * 1024;
+/// Longest chunk size or trailer line accepted by `ChunkedDecoder`.
+const MAX_CHUNK_LINE: u64 = 4096;
+/// Maximum number of trailer headers after a chunked body.
+const MAX_TRAILERS: usize = 64;
+
+/// Decodes a `Transfer-Encoding: chunked` body.
+///
+/// Chunk extensions are ignored and trailers are collected into `trailers`,
+/// malformed framing fails with a `400` and bodies bigger than `limit` with a `413`.
+/// After an error every read fails the same way, since the position in the framing is lost.
+#[derive(Debug)]
+pub struct ChunkedDecoder {
+    /// bytes left in the current chunk.
+    remaining: u64,
+    total: u64,
+    limit: u64,
+    done: bool,
+    failed: Option<Failure>,
+    pub trailers: HeaderMap,
 }
 
+impl ChunkedDecoder {
+    pub fn new(limit: u64) -> Self {
+        ChunkedDecoder {
+            remaining: 0,
+            total: 0,
+            limit,
+            done: false,
+            failed: None,
+            trailers: HeaderMap::new(),
+        }
+    }
+
+    /// Whether the last chunk and the trailers have been read.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Reads the next decoded bytes from `inner` into `buf`.
+    ///
+    /// ## Returns:
+    /// The number of bytes read, `0` once the body is complete.
+    pub fn read_from<R: BufRead + ?Sized>(
+        &mut self,
+        inner: &mut R,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        if let Some(failure) = &self.failed {
+            return Err(failure.error());
+        }
+
+        self.decode(inner, buf).inspect_err(|error| {
+            if error.kind() != io::ErrorKind::Interrupted {
+                self.failed = Some(Failure::new(error));
+            }
+        })
+    }
+
+    fn decode<R: BufRead + ?Sized>(&mut self, inner: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            self.read_size(inner)?;
+            if self.done {
+                return Ok(0);
+            }
+        }
+
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = inner.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(HttpError::new(400, "Incomplete chunk").into());
+        }
+
+        self.remaining -= read as u64;
+        self.total += read as u64;
+
+        if self.remaining == 0 && !read_chunk_line(inner)?.is_empty() {
+            return Err(HttpError::new(400, "Malformed chunk framing").into());
+        }
+
+        Ok(read)
+    }
+
+    fn read_size<R: BufRead + ?Sized>(&mut self, inner: &mut R) -> Result<(), err> {
+        let line = read_chunk_line(inner)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+
+        if size.is_empty() || !size.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(HttpError::new(400, "Invalid chunk size").into());
+        }
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| HttpError::new(413, "Request body is too large"))?;
+
+        if size == 0 {
+            self.read_trailers(inner)?;
+            self.done = true;
+            return Ok(());
+        }
+
+        if self.total.saturating_add(size) > self.limit {
+            return Err(HttpError::new(413, "Request body is too large").into());
+        }
+
+        self.remaining = size;
+        Ok(())
+    }
+
+    fn read_trailers<R: BufRead + ?Sized>(&mut self, inner: &mut R) -> Result<(), err> {
+        loop {
+            let line = read_chunk_line(inner)?;
+            if line.is_empty() {
+                return Ok(());
+            }
+
+            if self.trailers.len() >= MAX_TRAILERS {
+                return Err(HttpError::new(431, "Too many trailers").into());
+            }
+
+            let (key, val) = line
+                .split_once(':')
+                .ok_or(HttpError::new(400, "Malformed trailer"))?;
+            self.trailers
+                .append(key.trim(), val.trim())
+                .map_err(|_| HttpError::new(400, "Invalid trailer"))?;
+        }
+    }
+}
+
+/// Reads a CRLF terminated line of the chunked framing.
+fn read_chunk_line<R: BufRead + ?Sized>(inner: &mut R) -> Result<String, err> {
+    let mut line = Vec::new();
+    inner.take(MAX_CHUNK_LINE).read_until(b'\n', &mut line)?;
+
+    if !line.ends_with(b"\r\n") {
+        return Err(HttpError::new(400, "Malformed chunk framing").into());
+    }
+    line.truncate(line.len() - 2);
+
+    String::from_utf8(line).map_err(|_| HttpError::new(400, "Malformed chunk framing").into())
+}
+
+#[cfg(test)]
+mod chunked_tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn decodes_chunks_with_extensions_and_trailers() {
+        let raw =
+            "4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\nX-Sum: 1\r\nx-sum: 2\r\n\r\n";
+        let mut framing = Framing::Chunked(ChunkedDecoder::new(64));
+        let mut reader = BodyReader {
+            reader: &mut Cursor::new(raw),
+            framing: &mut framing,
+        };
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+
+        assert_eq!(body, "Wikipedia");
+        if let Framing::Chunked(decoder) = framing {
+            assert!(decoder.is_done());
+            assert_eq!(decoder.trailers.get("expires").unwrap(), "never");
+            assert_eq!(decoder.trailers.get_all("X-SUM"), ["1", "2"]);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_framing_and_big_bodies() {
+        let cases = [
+            ("z\r\nabc\r\n0\r\n\r\n", 400),
+            ("3\r\nabcd\r\n0\r\n\r\n", 400),
+            ("ff\r\n", 413),
+        ];
+
+        for (raw, status) in cases {
+            let mut decoder = ChunkedDecoder::new(16);
+            let error = decoder
+                .read_from(&mut Cursor::new(raw), &mut [0; 64])
+                .unwrap_err();
+            assert_eq!(HttpError::status_of(&error), Some(status));
+        }
+    }
+
+    #[test]
+    fn keeps_failing_after_an_error() {
+        // what follows the bad chunk must never be read as a body or a request.
+        let raw = "3\r\nabcX\r\n0\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n";
+        let mut cursor = Cursor::new(raw);
+        let mut framing = Framing::Chunked(ChunkedDecoder::new(64));
+        let mut reader = BodyReader {
+            reader: &mut cursor,
+            framing: &mut framing,
+        };
+
+        for _ in 0..2 {
+            let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+            assert_eq!(HttpError::status_of(&error), Some(400));
+        }
+        assert!(matches!(framing, Framing::Failed(_)));
+
+        let mut decoder = ChunkedDecoder::new(64);
+        let mut cursor = Cursor::new(raw);
+        let mut buf = [0; 64];
+        assert!(decoder.read_from(&mut cursor, &mut buf).is_err());
+        assert!(decoder.read_from(&mut cursor, &mut buf).is_err());
+    }
+}
@@ -2,18 +2,23 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
-use std::io::{Error as err, ErrorKind, Read, Write};
+use std::io::{self, Error as err, ErrorKind, Read, Write};
 use std::net::TcpStream;
 use std::rc::Rc;
 use std::sync::Weak;
 
 use super::*;
+use crate::cookie::Cookie;
+use crate::header::HeaderMap;
+use crate::json::Value;
 
 #[derive(Debug)]
 pub struct Response {
     status: u16,
     status_message: String,
-    headers: HashMap<String, String>,
+    headers: HeaderMap,
+    /// Cookies sent as separate `set-cookie` headers.
+    cookies: Vec<Cookie>,
     pub http_version: String,
     status_map: Weak<HashMap<u16, String>>,
     mime_map: Weak<HashMap<&'static str, &'static str>>,
@@ -21,6 +26,17 @@ pub struct Response {
     socket: Rc<RefCell<TcpStream>>,
     headers_sent: bool,
     body_sent: bool,
+    /// Body bytes written to the socket.
+    body_written: u64,
+    keep_alive: bool,
+    /// Whether the body is sent with `transfer-encoding: chunked`.
+    chunked: bool,
+    /// Whether the last chunk was sent.
+    chunked_done: bool,
+    /// Whether the client understands chunked bodies, HTTP/1.0 clients don't.
+    pub(crate) chunked_allowed: bool,
+    /// Whether the request was a `HEAD`, the headers are sent as usual but the body is left out.
+    pub(crate) head: bool,
 }
 
 impl Response {
@@ -33,6 +49,11 @@ impl Response {
         stream: Rc<RefCell<TcpStream>>,
         mime_map: Weak<HashMap<&'static str, &'static str>>,
     ) -> Self {
+        let mut headers = HeaderMap::new();
+        headers
+            .insert("content-type", "text/plain")
+            .unwrap_or_default();
+
         Response {
             status,
             status_message: status_map
@@ -41,20 +62,27 @@ impl Response {
                 .get(&status)
                 .unwrap_or(&"".to_string())
                 .clone(),
-            headers: HashMap::from([("content-type".to_string(), "text/plain".to_string())]),
+            headers,
+            cookies: Vec::new(),
             http_version: String::from("HTTP/1.1"),
             status_map,
             mime_map,
             headers_sent: false,
             body_sent: false,
+            body_written: 0,
+            keep_alive: true,
+            chunked: false,
+            chunked_done: false,
+            chunked_allowed: true,
+            head: false,
             socket: stream,
         }
     }
 
-    /// Sets A header and return s a `Result`
+    /// Sets A header and return s a `Result`, replacing any previous values.
     ///
     /// ## Fails:
-    /// if the headers Have been sent already.
+    /// if the headers Have been sent already or the name or value are invalid.
     pub fn set_header(&mut self, header: &str, value: &str) -> Result<(), err> {
         if self.headers_sent {
             return Err(err::new(
@@ -63,16 +91,70 @@ impl Response {
             ));
         }
 
-        self.headers.insert(header.to_string(), value.to_string());
-        Ok(())
+        self.headers.insert(header, value)
+    }
+
+    /// Adds a value to a header without replacing the previous ones.
+    ///
+    /// ## Fails:
+    /// if the headers Have been sent already or the name or value are invalid.
+    pub fn append_header(&mut self, header: &str, value: &str) -> Result<(), err> {
+        if self.headers_sent {
+            return Err(err::new(
+                ErrorKind::InvalidInput,
+                "Cannot Write Headers After they're sent!",
+            ));
+        }
+
+        self.headers.append(header, value)
     }
 
     /// ## Returns:
-    /// An `Option` with the specified header or `None`
+    /// An `Option` with the first value of the specified header or `None`, the name is case-insensitive.
     pub fn get_header(&self, header: &str) -> Option<&String> {
         self.headers.get(header)
     }
 
+    /// Removes every value of a header.
+    pub fn remove_header(&mut self, header: &str) -> Result<(), err> {
+        if self.headers_sent {
+            return Err(err::new(
+                ErrorKind::InvalidInput,
+                "Cannot Write Headers After they're sent!",
+            ));
+        }
+
+        self.headers.remove(header);
+        Ok(())
+    }
+
+    /// Adds a cookie to the response, every cookie is sent in its own `set-cookie` header.
+    ///
+    /// A cookie with the same name, path and domain replaces the previous one.
+    ///
+    /// ## Fails:
+    /// if the headers have been sent already or the cookie is invalid.
+    pub fn set_cookie(&mut self, cookie: Cookie) -> Result<(), err> {
+        if self.headers_sent {
+            return Err(err::new(
+                ErrorKind::InvalidInput,
+                "Cannot Write Headers After they're sent!",
+            ));
+        }
+
+        cookie.validate()?;
+        self.cookies.retain(|c| {
+            c.name != cookie.name || c.path != cookie.path || c.domain != cookie.domain
+        });
+        self.cookies.push(cookie);
+        Ok(())
+    }
+
+    /// Tells the client to remove the cookie `name` set with the `/` path.
+    pub fn remove_cookie(&mut self, name: &str) -> Result<(), err> {
+        self.set_cookie(Cookie::removal(name).path("/"))
+    }
+
     /// Sets status code and status message automatically based on the status map.
     ///
     /// ## Fails:
@@ -101,6 +183,45 @@ impl Response {
         self.status
     }
 
+    /// Current response status message.
+    pub fn status_message(&self) -> &str {
+        &self.status_message
+    }
+
+    /// Whether the headers have been sent, after that the status and headers can't be changed.
+    pub fn is_committed(&self) -> bool {
+        self.headers_sent
+    }
+
+    /// Whether the connection can be reused for another request after this response.
+    ///
+    /// That's only the case if the response was sent with proper framing,
+    /// a `content-length` that matches the body or a status that has no body.
+    pub fn keep_alive(&self) -> bool {
+        if !self.keep_alive || !self.headers_sent {
+            return false;
+        }
+        if self.head {
+            return true;
+        }
+        if self.chunked {
+            return self.chunked_done;
+        }
+
+        match self.content_length() {
+            Some(length) => length == self.body_written,
+            None => self.is_bodyless(),
+        }
+    }
+
+    /// Marks the connection to be closed after this response.
+    pub fn close_connection(&mut self) {
+        self.keep_alive = false;
+        if !self.headers_sent {
+            self.set_header("connection", "close").unwrap_or_default();
+        }
+    }
+
     /// Sets the content type for the response
     pub fn set_content_type(&mut self, c_type: &str) -> Result<(), err> {
         self.set_header("content-type", c_type)?;
@@ -133,6 +254,9 @@ impl Response {
 
         self.check_headers()?;
         self.body_sent = true;
+        if self.head {
+            return Ok(());
+        }
 
         let mut buf = [0; 2048];
         let mut stream = self.socket.try_borrow_mut()?;
@@ -142,6 +266,7 @@ impl Response {
                 break;
             }
             stream.write_all(&buf[..chunk])?;
+            self.body_written += chunk as u64;
         }
 
         Ok(())
@@ -149,6 +274,8 @@ impl Response {
 
     /// Pipes a stream to the response, sending all the data.
     ///
+    /// If no `content-length` was set the data is sent with `transfer-encoding: chunked`.
+    ///
     /// ## Returns:
     /// an error of the socket is busy or the stream fails.
     ///
@@ -157,6 +284,27 @@ impl Response {
     where
         T: Read,
     {
+        let mut writer = self.writer()?;
+        // on an error the writer is dropped unfinished, so the client sees a truncated body.
+        io::copy(stream, &mut writer)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    /// Returns a writer to send the body incrementally.
+    ///
+    /// If no `content-length` was set the body is sent with `transfer-encoding: chunked`,
+    /// every write is sent as a chunk and trailers can be added with `ResponseWriter::trailer`.
+    /// The response is completed by `ResponseWriter::finish`, a writer dropped without it
+    /// (after an error or a panic) aborts the response: the last chunk isn't sent and the
+    /// connection is closed, so the client can tell the body is incomplete.
+    ///
+    /// HTTP/1.0 clients get the body unframed and the connection is closed after it.
+    ///
+    /// ## Fails:
+    /// if some other data is already sent or the headers can't be written.
+    pub fn writer(&mut self) -> Result<ResponseWriter<'_>, Box<dyn Error>> {
         if self.body_sent {
             return Err(Box::new(err::new(
                 ErrorKind::AlreadyExists,
@@ -164,38 +312,65 @@ impl Response {
             )));
         }
 
+        if self.content_length().is_none() && !self.is_bodyless() && self.chunked_allowed {
+            self.set_header("transfer-encoding", "chunked")?;
+            self.chunked = true;
+        }
+
         self.body_sent = true;
         self.check_headers()?;
 
-        let mut buf = [0; 2048];
-        let mut socket = self.socket.try_borrow_mut()?;
-
-        while let Ok(chunk) = stream.read(&mut buf[..]) {
-            if chunk == 0 {
-                break;
-            }
-            socket.write_all(&buf[..chunk])?;
-        }
-
-        Ok(())
+        Ok(ResponseWriter {
+            res: self,
+            trailers: HeaderMap::new(),
+            finished: false,
+        })
     }
 
-    /// Write `data` to the response.
+    /// Write `data` to the response, it can be text or binary data.
+    ///
+    /// The `content-length` header is set automatically, see `send_bytes`.
     ///
     /// ## Returns:
-    /// an error if writing fails or if you're using the stream in a different place (the `Request` for example).
-    pub fn send(&mut self, data: &str) -> Result<(), Box<dyn Error>> {
+    /// an error if writing fails, if a body was already sent
+    /// or if you're using the stream in a different place (the `Request` for example).
+    pub fn send<T: AsRef<[u8]>>(&mut self, data: T) -> Result<(), Box<dyn Error>> {
+        self.send_bytes(data.as_ref())
+    }
+
+    /// Sends `data` as the whole body with a matching `content-length`.
+    ///
+    /// ## Fails:
+    /// if some other data is already sent, the body can only be sent once.
+    pub fn send_bytes(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        if self.body_sent {
+            return Err(Box::new(err::new(
+                ErrorKind::AlreadyExists,
+                "Soem other data is already sent.",
+            )));
+        }
+
+        self.set_header("content-length", data.len().to_string().as_str())?;
         self.body_sent = true;
         self.check_headers()?;
-        self.socket.try_borrow_mut()?.write_all(data.as_bytes())?;
+        self.write_body(data)?;
         Ok(())
     }
 
+    /// Serializes `value` and sends it with the `application/json` content type.
+    pub fn send_json(&mut self, value: &Value) -> Result<(), Box<dyn Error>> {
+        let body = value.to_string();
+
+        self.set_content_type("application/json")?;
+        self.send(body)
+    }
+
     /// Ends the response and closes the stream.
     ///
     /// ## Deprecated:
     /// This is useless as unexpected.
     pub fn end(&mut self) -> Result<(), Box<dyn Error>> {
+        self.close_connection();
         self.body_sent = true;
         self.check_headers()?;
         self.socket
@@ -205,7 +380,7 @@ impl Response {
     }
 
     fn check_headers(&mut self) -> Result<(), Box<dyn Error>> {
-        if self.headers_sent == false {
+        if !self.headers_sent {
             let res = self.create_response();
             self.socket.try_borrow_mut()?.write_all(res.as_bytes())?;
             self.headers_sent = true;
@@ -214,7 +389,67 @@ impl Response {
         Ok(())
     }
 
+    /// Writes part of the body, as a chunk if the response is chunked.
+    fn write_body(&mut self, data: &[u8]) -> Result<(), err> {
+        if data.is_empty() || self.head {
+            return Ok(());
+        }
+
+        let mut socket = self.socket.try_borrow_mut().map_err(err::other)?;
+
+        if self.chunked {
+            socket.write_all(format!("{:x}\r\n", data.len()).as_bytes())?;
+            socket.write_all(data)?;
+            socket.write_all(b"\r\n")?;
+        } else {
+            socket.write_all(data)?;
+        }
+
+        self.body_written += data.len() as u64;
+        Ok(())
+    }
+
+    /// Sends the last chunk followed by `trailers`.
+    fn finish_chunked(&mut self, trailers: &HeaderMap) -> Result<(), err> {
+        if !self.chunked || self.chunked_done || self.head {
+            return Ok(());
+        }
+
+        let mut end = String::from("0\r\n");
+        for (key, val) in trailers.iter() {
+            end.push_str(format!("{key}: {val}\r\n").as_str());
+        }
+        end.push_str("\r\n");
+
+        self.socket
+            .try_borrow_mut()
+            .map_err(err::other)?
+            .write_all(end.as_bytes())?;
+        self.chunked_done = true;
+        Ok(())
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.headers.get("content-length")?.parse().ok()
+    }
+
+    /// Responses with these statuses never have a body.
+    fn is_bodyless(&self) -> bool {
+        matches!(self.status, 100..=199 | 204 | 304)
+    }
+
     fn create_response(&mut self) -> String {
+        let closing = self
+            .headers
+            .get("connection")
+            .is_some_and(|val| val.eq_ignore_ascii_case("close"));
+
+        // without framing the client can only find the end of the body when the connection closes.
+        let unframed = self.content_length().is_none() && !self.chunked && !self.is_bodyless();
+        if closing || (unframed && !self.head) {
+            self.close_connection();
+        }
+
         let first = format!(
             "{} {} {}\r\n",
             self.http_version, self.status, self.status_message
@@ -225,7 +460,113 @@ impl Response {
             headers.push_str(format!("{key}: {val}\r\n").as_str());
         }
 
+        for cookie in self.cookies.iter() {
+            headers.push_str(format!("set-cookie: {cookie}\r\n").as_str());
+        }
+
         format!("{first}{headers}\r\n")
     }
 }
 
+/// Writes the body of a `Response` incrementally, see `Response::writer`.
+pub struct ResponseWriter<'a> {
+    res: &'a mut Response,
+    trailers: HeaderMap,
+    finished: bool,
+}
+
+impl ResponseWriter<'_> {
+    /// Adds a trailer sent after the last chunk, ignored if the response isn't chunked.
+    ///
+    /// ## Fails:
+    /// if the name or the value are invalid.
+    pub fn trailer(&mut self, name: &str, value: &str) -> Result<(), err> {
+        self.trailers.append(name, value)
+    }
+
+    /// Completes the response, sending the last chunk and the trailers.
+    pub fn finish(mut self) -> Result<(), err> {
+        self.finished = true;
+        self.res.finish_chunked(&self.trailers)
+    }
+}
+
+impl Write for ResponseWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.res.write_body(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.res
+            .socket
+            .try_borrow_mut()
+            .map_err(err::other)?
+            .flush()
+    }
+}
+
+/// An unfinished writer aborts the response, see `Response::writer`.
+impl Drop for ResponseWriter<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.res.close_connection();
+        }
+    }
+}
+
+#[cfg(test)]
+mod writer_tests {
+    use super::*;
+    use crate::testing::Connection;
+
+    /// Fails after the first read, like a file on a broken disk.
+    struct Broken(bool);
+
+    impl Read for Broken {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0 {
+                return Err(err::other("read failed"));
+            }
+            self.0 = true;
+            buf[..3].copy_from_slice(b"abc");
+            Ok(3)
+        }
+    }
+
+    #[test]
+    fn frames_chunks_and_trailers() {
+        let mut conn = Connection::open("");
+        let mut res = conn.response();
+
+        let mut writer = res.writer().unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.write_all(b" world!").unwrap();
+        writer.trailer("x-checksum", "abc").unwrap();
+        writer.finish().unwrap();
+        assert!(res.keep_alive());
+
+        let out = conn.received();
+        assert!(out.contains("transfer-encoding: chunked\r\n"));
+        assert!(out.ends_with("\r\n\r\n5\r\nhello\r\n7\r\n world!\r\n0\r\nx-checksum: abc\r\n\r\n"));
+    }
+
+    #[test]
+    fn aborts_unfinished_bodies() {
+        let mut conn = Connection::open("");
+        let mut res = conn.response();
+        assert!(res.stream(&mut Broken(false)).is_err());
+        assert!(!res.keep_alive());
+        assert!(conn.received().ends_with("\r\n\r\n3\r\nabc\r\n"));
+
+        // a writer dropped on an error doesn't complete the body either, even with a length.
+        let mut conn = Connection::open("");
+        let mut res = conn.response();
+        res.set_header("content-length", "6").unwrap();
+        let mut writer = res.writer().unwrap();
+        writer.write_all(b"abcdef").unwrap();
+        drop(writer);
+        assert!(!res.keep_alive());
+        assert!(conn.received().ends_with("\r\n\r\nabcdef"));
+    }
+}
@@ -1,5 +1,11 @@
+use std::collections::HashMap;
 use std::error::Error;
+use std::mem;
+use std::sync::Arc;
 
+use crate::constraint::Constraint;
+use crate::controller;
+use crate::method::Method;
 use crate::{request::Request, response::Response};
 
 pub type RouteResult = Result<bool, Box<dyn Error>>;
@@ -11,3 +17,673 @@ pub struct Route {
     pub method: &'static str,
     pub path: &'static str,
 }
+
+/// Values captured by `:name` and `*name` segments, in the order they appear in the path.
+pub type Params = Vec<(String, String)>;
+
+/// Every handler registered on a server.
+pub(crate) struct Routes {
+    /// Route handlers, one tree per method.
+    pub methods: HashMap<Method, Node>,
+    pub global: Vec<Route>,
+    pub finals: Vec<Route>,
+    pub errors: Vec<Route>,
+    pub not_found: Route,
+    pub unhandled_error: Route,
+}
+
+/// No routes yet, with the default not found and unhandled error handlers.
+impl Default for Routes {
+    fn default() -> Self {
+        Routes {
+            methods: HashMap::new(),
+            global: Vec::new(),
+            finals: Vec::new(),
+            errors: Vec::new(),
+            not_found: fallback(controller::default_not_found()),
+            unhandled_error: fallback(controller::default_unhandled_error()),
+        }
+    }
+}
+
+impl Routes {
+    /// Adds the routes of `router` under `prefix`.
+    ///
+    /// Handlers are wrapped so they see `req.path` without the prefix, and middlewares,
+    /// finals and error handlers only run for requests under it.
+    pub fn mount(&mut self, prefix: &str, router: Router) {
+        check_prefix(prefix);
+        let prefix: Arc<str> = Arc::from(prefix.trim_end_matches('/'));
+
+        for route in router.routes {
+            let path = match route.path {
+                "/" if !prefix.is_empty() => prefix.to_string(),
+                path => format!("{prefix}{path}"),
+            };
+
+            self.methods
+                .entry(Method::from_route(route.method))
+                .or_default()
+                .insert(&path, scoped(&prefix, None, route));
+        }
+
+        self.global.extend(
+            router
+                .middlewares
+                .into_iter()
+                .map(|route| scoped(&prefix, Some(route.path), route)),
+        );
+        self.finals.extend(
+            router
+                .finals
+                .into_iter()
+                .map(|route| scoped(&prefix, Some(route.path), route)),
+        );
+        self.errors.extend(
+            router
+                .errors
+                .into_iter()
+                .map(|route| scoped(&prefix, Some(route.path), route)),
+        );
+
+        for (inner, router) in router.routers {
+            self.mount(&format!("{prefix}{}", inner.trim_end_matches('/')), router);
+        }
+    }
+
+    /// Returns the methods with a route matching `path`, sorted by name.
+    ///
+    /// `HEAD` is implied by `GET` and `OPTIONS` is always allowed once another method matches.
+    /// The `*` of `OPTIONS *` stands for the whole server, so it matches every method with a route.
+    pub fn allowed(&self, path: &str) -> Vec<Method> {
+        let mut allowed = self
+            .methods
+            .iter()
+            .filter(|(_, tree)| path == "*" || !tree.find(path).is_empty())
+            .map(|(method, _)| method.clone())
+            .collect::<Vec<_>>();
+        if allowed.is_empty() {
+            return allowed;
+        }
+
+        if allowed.contains(&Method::Get) && !allowed.contains(&Method::Head) {
+            allowed.push(Method::Head);
+        }
+        if !allowed.contains(&Method::Options) {
+            allowed.push(Method::Options);
+        }
+        allowed.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        allowed
+    }
+}
+
+/// A route that matches any method and path, used for the not found and unhandled error handlers.
+pub(crate) fn fallback(handler: RouteHandler) -> Route {
+    Route {
+        handler,
+        path: "*",
+        method: "*",
+    }
+}
+
+/// Wraps a mounted route so it runs with `req.path` relative to `prefix`.
+///
+/// When `path` is set the route only runs for requests to `prefix` followed by `path`,
+/// or anything under `prefix` if it's `*`, and the wrapped route matches every path.
+fn scoped(prefix: &Arc<str>, path: Option<&'static str>, route: Route) -> Route {
+    let prefix = prefix.clone();
+    let handler = route.handler;
+
+    Route {
+        method: route.method,
+        path: if path.is_some() { "*" } else { route.path },
+        handler: Box::new(move |req, res| {
+            let Some(rest) = strip_prefix(&req.path, &prefix).map(str::to_string) else {
+                return Ok(false);
+            };
+            if path.is_some_and(|path| path != "*" && path != rest) {
+                return Ok(false);
+            }
+
+            let full = mem::replace(&mut req.path, rest);
+            let result = handler(req, res);
+            req.path = full;
+            result
+        }),
+    }
+}
+
+/// Panics if a segment of `prefix` is a parameter, prefixes are only matched as static text.
+fn check_prefix(prefix: &str) {
+    assert!(
+        !prefix
+            .split('/')
+            .any(|segment| segment.starts_with([':', '*', '{'])),
+        "Mount prefix {prefix} can't have parameters"
+    );
+}
+
+/// Returns what's left of `path` after `prefix`, `/` if nothing is left.
+fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    match path.strip_prefix(prefix)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+/// A group of routes with its own middlewares, added to a server with `HTTPServer::mount`.
+///
+/// Paths are relative to the prefix the router is mounted at, and so is `req.path` in its handlers.
+/// Middlewares, finals and error handlers of a router only run for requests under its prefix.
+/// ```
+/// use stress::router::Router;
+///
+/// let mut api = Router::new();
+/// api.middleware("*", Box::new(|_req, res| {
+///     res.set_header("cache-control", "no-store")?;
+///     Ok(false)
+/// }));
+/// api.get("/users/:id", Box::new(|req, res| {
+///     res.send(format!("user {}", req.param("id").unwrap()))?;
+///     Ok(true)
+/// }));
+/// ```
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    middlewares: Vec<Route>,
+    finals: Vec<Route>,
+    errors: Vec<Route>,
+    /// Routers mounted inside this one, with their prefixes.
+    routers: Vec<(String, Router)>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    /// Defines a route with the specified `method`, `path` and `handler`, see `HTTPServer::register`.
+    pub fn register(&mut self, method: &'static str, path: &'static str, handler: RouteHandler) {
+        self.routes.push(Route {
+            handler,
+            path,
+            method,
+        });
+    }
+
+    /// Creates a middleware for `path`, or every path under the prefix with `*`.
+    pub fn middleware(&mut self, path: &'static str, handler: RouteHandler) {
+        self.middlewares.push(Route {
+            handler,
+            path,
+            method: "*",
+        });
+    }
+
+    pub fn last(&mut self, path: &'static str, handler: RouteHandler) {
+        self.finals.push(Route {
+            handler,
+            path,
+            method: "*",
+        });
+    }
+
+    pub fn error_ware(&mut self, method: &'static str, path: &'static str, handler: RouteHandler) {
+        self.errors.push(Route {
+            handler,
+            path,
+            method,
+        });
+    }
+
+    /// Mounts `router` under `prefix` inside this router.
+    ///
+    /// Panics if `prefix` has parameters, see `HTTPServer::mount`.
+    pub fn mount(&mut self, prefix: &str, router: Router) {
+        check_prefix(prefix);
+        self.routers.push((prefix.to_string(), router));
+    }
+
+    /// A wrapper for `Router.register()`.
+    pub fn get(&mut self, path: &'static str, handler: RouteHandler) {
+        self.register("GET", path, handler);
+    }
+
+    /// A wrapper for `Router.register()`.
+    pub fn post(&mut self, path: &'static str, handler: RouteHandler) {
+        self.register("POST", path, handler);
+    }
+
+    /// A wrapper for `Router.register()`.
+    pub fn put(&mut self, path: &'static str, handler: RouteHandler) {
+        self.register("PUT", path, handler);
+    }
+
+    /// A wrapper for `Router.register()`.
+    pub fn delete(&mut self, path: &'static str, handler: RouteHandler) {
+        self.register("DELETE", path, handler);
+    }
+
+    /// A wrapper for `Router.register()`.
+    pub fn patch(&mut self, path: &'static str, handler: RouteHandler) {
+        self.register("PATCH", path, handler);
+    }
+}
+
+/// A radix tree node matching paths to routes.
+///
+/// Static text is shared between routes with the same prefix, `:name` matches one segment
+/// and `*name` matches the rest of the path. Static text is tried before parameters and
+/// parameters before catch-alls.
+#[derive(Default)]
+pub(crate) struct Node {
+    /// The static text this node matches.
+    prefix: String,
+    /// Static children, no two of them start with the same byte.
+    children: Vec<Node>,
+    /// `:name` and `{name:constraint}` children, constrained ones are tried first.
+    params: Vec<Param>,
+    /// Routes ending in `*name` after this node.
+    catch_all: Option<(String, Vec<Route>)>,
+    /// Routes ending at this node.
+    routes: Vec<Route>,
+}
+
+/// A parameter segment and the routes below it.
+struct Param {
+    name: String,
+    /// The constraint as written in the path, parameters are shared when it's the same.
+    source: Option<String>,
+    constraint: Option<Constraint>,
+    node: Node,
+}
+
+/// A part of a route path.
+enum Token<'a> {
+    Static(String),
+    /// A name and the source of its constraint.
+    Param(&'a str, Option<&'a str>),
+    CatchAll(&'a str),
+}
+
+impl Node {
+    /// Adds `route` to the tree under `path`.
+    ///
+    /// ## Panics:
+    /// if a `*name` segment isn't the last one in the path or a constraint is invalid.
+    pub fn insert(&mut self, path: &str, route: Route) {
+        let tokens = tokenize(path);
+        self.insert_tokens(&tokens, route);
+    }
+
+    /// Finds every route matching `path` with the values it captured, best match first.
+    pub fn find(&self, path: &str) -> Vec<(&Route, Params)> {
+        let mut matches = Vec::new();
+        self.collect(path, &mut Vec::new(), &mut matches);
+        matches
+    }
+
+    fn insert_tokens(&mut self, tokens: &[Token], route: Route) {
+        match tokens.first() {
+            None => self.routes.push(route),
+            Some(Token::Static(text)) => self.insert_static(text, &tokens[1..], route),
+            Some(Token::Param(name, source)) => {
+                let existing = self
+                    .params
+                    .iter()
+                    .position(|param| param.name == *name && param.source.as_deref() == *source);
+
+                let index = existing.unwrap_or_else(|| {
+                    let constraint = source.map(|source| {
+                        Constraint::parse(source)
+                            .unwrap_or_else(|e| panic!("{e} in {}", route.path))
+                    });
+                    // constrained parameters go before the ones that match anything.
+                    let index = match constraint {
+                        Some(_) => self
+                            .params
+                            .iter()
+                            .position(|param| param.constraint.is_none())
+                            .unwrap_or(self.params.len()),
+                        None => self.params.len(),
+                    };
+
+                    self.params.insert(
+                        index,
+                        Param {
+                            name: name.to_string(),
+                            source: source.map(str::to_string),
+                            constraint,
+                            node: Node::default(),
+                        },
+                    );
+                    index
+                });
+                self.params[index].node.insert_tokens(&tokens[1..], route);
+            }
+            Some(Token::CatchAll(name)) => {
+                assert!(
+                    tokens.len() == 1,
+                    "`*{name}` must be the end of {}",
+                    route.path
+                );
+
+                match &mut self.catch_all {
+                    Some((_, routes)) => routes.push(route),
+                    None => self.catch_all = Some((name.to_string(), vec![route])),
+                }
+            }
+        }
+    }
+
+    /// Inserts `text` below this node, splitting the child that shares part of it.
+    fn insert_static(&mut self, text: &str, rest: &[Token], route: Route) {
+        let first = text.as_bytes()[0];
+        let Some(child) = self
+            .children
+            .iter_mut()
+            .find(|child| child.prefix.as_bytes()[0] == first)
+        else {
+            let mut child = Node {
+                prefix: text.to_string(),
+                ..Node::default()
+            };
+            child.insert_tokens(rest, route);
+            return self.children.push(child);
+        };
+
+        let common = common_prefix(&child.prefix, text);
+        if common < child.prefix.len() {
+            // the child keeps the shared part and its old contents move one level down.
+            let suffix = child.prefix.split_off(common);
+            let moved = Node {
+                prefix: suffix,
+                ..mem::take(child)
+            };
+            child.prefix = text[..common].to_string();
+            child.children.push(moved);
+        }
+
+        if common == text.len() {
+            child.insert_tokens(rest, route);
+        } else {
+            child.insert_static(&text[common..], rest, route);
+        }
+    }
+
+    /// `path` is what's left of the request path after this node's prefix.
+    fn collect<'a>(
+        &'a self,
+        path: &str,
+        params: &mut Params,
+        matches: &mut Vec<(&'a Route, Params)>,
+    ) {
+        if path.is_empty() {
+            matches.extend(self.routes.iter().map(|route| (route, params.clone())));
+        }
+
+        if let Some(child) = path.as_bytes().first().and_then(|first| {
+            self.children
+                .iter()
+                .find(|c| c.prefix.as_bytes()[0] == *first)
+        }) {
+            if let Some(rest) = path.strip_prefix(child.prefix.as_str()) {
+                child.collect(rest, params, matches);
+            }
+        }
+
+        let end = path.find('/').unwrap_or(path.len());
+        if end > 0 {
+            let value = &path[..end];
+
+            // a value the constraint rejects falls through to the next parameter.
+            for param in self.params.iter().filter(|param| {
+                param
+                    .constraint
+                    .as_ref()
+                    .is_none_or(|constraint| constraint.matches(value))
+            }) {
+                params.push((param.name.clone(), value.to_string()));
+                param.node.collect(&path[end..], params, matches);
+                params.pop();
+            }
+        }
+
+        if let Some((name, routes)) = &self.catch_all {
+            params.push((name.clone(), path.to_string()));
+            matches.extend(routes.iter().map(|route| (route, params.clone())));
+            params.pop();
+        }
+    }
+}
+
+/// Splits a route path into static text, `:name`, `{name:constraint}` and `*name` segments.
+fn tokenize(path: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+
+    for (i, segment) in path.split('/').enumerate() {
+        if i > 0 {
+            text.push('/');
+        }
+
+        let braced = segment
+            .strip_prefix('{')
+            .and_then(|segment| segment.strip_suffix('}'));
+
+        let token = if let Some(name) = segment.strip_prefix(':') {
+            Token::Param(name, None)
+        } else if let Some(param) = braced {
+            match param.split_once(':') {
+                Some((name, source)) => Token::Param(name, Some(source)),
+                None => Token::Param(param, None),
+            }
+        } else if let Some(name) = segment.strip_prefix('*') {
+            Token::CatchAll(name)
+        } else {
+            text.push_str(segment);
+            continue;
+        };
+
+        if !text.is_empty() {
+            tokens.push(Token::Static(mem::take(&mut text)));
+        }
+        tokens.push(token);
+    }
+
+    if !text.is_empty() {
+        tokens.push(Token::Static(text));
+    }
+
+    tokens
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.bytes().zip(b.bytes()).take_while(|(a, b)| a == b).count()
+}
+
+#[cfg(test)]
+mod router_tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use crate::testing::Connection;
+
+    fn route(path: &'static str) -> Route {
+        Route {
+            handler: Box::new(|_, _| Ok(true)),
+            method: "GET",
+            path,
+        }
+    }
+
+    fn find(tree: &Node, path: &str) -> Vec<(&'static str, Params)> {
+        tree.find(path)
+            .into_iter()
+            .map(|(route, params)| (route.path, params))
+            .collect()
+    }
+
+    #[test]
+    fn matches_by_precedence() {
+        let mut tree = Node::default();
+        for path in [
+            "/users/:id",
+            "/users/me",
+            "/users/:id/posts",
+            "/files/*rest",
+            "/",
+            "/user",
+        ] {
+            tree.insert(path, route(path));
+        }
+
+        let id = |val: &str| vec![("id".to_string(), val.to_string())];
+
+        assert_eq!(
+            find(&tree, "/users/me"),
+            [("/users/me", vec![]), ("/users/:id", id("me"))]
+        );
+        assert_eq!(find(&tree, "/users/42"), [("/users/:id", id("42"))]);
+        assert_eq!(
+            find(&tree, "/users/42/posts"),
+            [("/users/:id/posts", id("42"))]
+        );
+        assert_eq!(find(&tree, "/user"), [("/user", vec![])]);
+        assert_eq!(find(&tree, "/"), [("/", vec![])]);
+        assert!(find(&tree, "/users/").is_empty());
+        assert!(find(&tree, "/users").is_empty());
+        assert_eq!(
+            find(&tree, "/files/a/b.txt"),
+            [(
+                "/files/*rest",
+                vec![("rest".to_string(), "a/b.txt".to_string())]
+            )]
+        );
+    }
+
+    #[test]
+    fn constraints_fall_through() {
+        let mut tree = Node::default();
+        for path in ["/users/:name", "/users/{id:u64}", "/lang/{code:en|fr}"] {
+            tree.insert(path, route(path));
+        }
+
+        let param = |name: &str, val: &str| vec![(name.to_string(), val.to_string())];
+
+        assert_eq!(
+            find(&tree, "/users/42"),
+            [
+                ("/users/{id:u64}", param("id", "42")),
+                ("/users/:name", param("name", "42"))
+            ]
+        );
+        assert_eq!(
+            find(&tree, "/users/bob"),
+            [("/users/:name", param("name", "bob"))]
+        );
+        assert_eq!(
+            find(&tree, "/lang/fr"),
+            [("/lang/{code:en|fr}", param("code", "fr"))]
+        );
+        assert!(find(&tree, "/lang/de").is_empty());
+    }
+
+    /// Runs the global middlewares and the first route matching a `GET` to `path`,
+    /// the handlers of `routes` record what they see in `seen`.
+    fn run(routes: &Routes, path: &str, seen: &Mutex<Vec<String>>) -> Vec<String> {
+        let conn = Connection::open(&format!("GET {path} HTTP/1.1\r\n\r\n"));
+        let mut req = conn.request();
+        let mut res = conn.response();
+
+        for route in routes.global.iter() {
+            (route.handler)(&mut req, &mut res).unwrap();
+        }
+        if let Some((route, params)) = routes.methods[&Method::Get].find(path).into_iter().next() {
+            req.params = params;
+            (route.handler)(&mut req, &mut res).unwrap();
+        }
+        assert_eq!(req.path, path);
+
+        mem::take(&mut *seen.lock().unwrap())
+    }
+
+    #[test]
+    fn mounts_routers_under_a_prefix() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = |label: &'static str| -> RouteHandler {
+            let seen = seen.clone();
+            Box::new(move |req, _| {
+                seen.lock().unwrap().push(format!("{label} {}", req.path));
+                Ok(true)
+            })
+        };
+
+        let mut v1 = Router::new();
+        v1.middleware("*", record("v1 middleware"));
+        v1.get("/users/:id", record("user"));
+        let mut api = Router::new();
+        api.middleware("/", record("api middleware"));
+        api.get("/", record("api"));
+        api.mount("/v1/", v1);
+
+        let mut routes = Routes::default();
+        routes.mount("/api/", api);
+
+        assert_eq!(run(&routes, "/api", &seen), ["api middleware /", "api /"]);
+        assert_eq!(
+            run(&routes, "/api/v1/users/7", &seen),
+            ["v1 middleware /users/7", "user /users/7"]
+        );
+        assert!(run(&routes, "/apiv1/users/7", &seen).is_empty());
+
+        let tree = &routes.methods[&Method::Get];
+        assert_eq!(
+            find(tree, "/api/v1/users/7"),
+            [("/users/:id", vec![("id".to_string(), "7".to_string())])]
+        );
+        assert!(find(tree, "/v1/users/7").is_empty());
+        // the `/` route of a router is the prefix itself, like any path a trailing slash differs.
+        assert!(find(tree, "/api/").is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Mount prefix /users/:id can't have parameters")]
+    fn rejects_prefixes_with_parameters() {
+        Router::new().mount("/users/:id", Router::new());
+    }
+
+    #[test]
+    fn lists_allowed_methods() {
+        let mut routes = Routes::default();
+        for (method, path) in [(Method::Get, "/users/:id"), (Method::Delete, "/users/:id")] {
+            routes
+                .methods
+                .entry(method)
+                .or_default()
+                .insert(path, route(path));
+        }
+        routes
+            .methods
+            .entry(Method::from("PROPFIND"))
+            .or_default()
+            .insert("/dav", route("/dav"));
+
+        let allowed = |path| {
+            routes
+                .allowed(path)
+                .iter()
+                .map(Method::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(allowed("/users/1"), ["DELETE", "GET", "HEAD", "OPTIONS"]);
+        assert_eq!(allowed("/dav"), ["OPTIONS", "PROPFIND"]);
+        assert!(allowed("/posts").is_empty());
+        assert_eq!(
+            allowed("*"),
+            ["DELETE", "GET", "HEAD", "OPTIONS", "PROPFIND"]
+        );
+    }
+}
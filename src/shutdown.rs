@@ -0,0 +1,183 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Stops a running server, get one with `HTTPServer::shutdown_handle`.
+///
+/// Handles can be cloned and sent to other threads, every clone stops the same server.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    state: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    requested: AtomicBool,
+    /// Address of the listener while the server is accepting connections.
+    addr: Mutex<Option<SocketAddr>>,
+}
+
+impl ShutdownHandle {
+    /// Asks the server to shut down, `listen` returns once the workers are done.
+    ///
+    /// Calling it again does nothing, if the server isn't listening yet the next `listen`
+    /// returns right away.
+    pub fn shutdown(&self) {
+        self.state.requested.store(true, Ordering::SeqCst);
+
+        // the listener is blocked waiting for a connection, so one is made to wake it up.
+        let addr = *self
+            .state
+            .addr
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(mut addr) = addr {
+            if addr.ip().is_unspecified() {
+                addr.set_ip(match addr.ip() {
+                    IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                });
+            }
+            TcpStream::connect_timeout(&addr, Duration::from_secs(1)).ok();
+        }
+    }
+
+    /// Whether a shutdown was requested.
+    pub fn is_shutdown(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+
+    /// Sets the address `shutdown` connects to, `None` once the listener is closed.
+    pub(crate) fn set_addr(&self, addr: Option<SocketAddr>) {
+        *self
+            .state
+            .addr
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = addr;
+    }
+
+    /// Clears the request so the server can listen again.
+    pub(crate) fn reset(&self) {
+        self.state.requested.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Shuts the server down on SIGINT or SIGTERM until `handle` is shut down.
+///
+/// The previous signal handlers are restored after the first signal, so a second one
+/// stops the process right away.
+pub(crate) fn watch_signals(handle: ShutdownHandle) -> JoinHandle<()> {
+    signals::install();
+
+    thread::spawn(move || {
+        let mut installed = true;
+
+        while !handle.is_shutdown() {
+            if signals::take() {
+                signals::restore();
+                installed = false;
+                handle.shutdown();
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        if installed {
+            signals::restore();
+        }
+    })
+}
+
+#[cfg(unix)]
+mod signals {
+    use std::os::raw::c_int;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+    static PREVIOUS_INT: AtomicUsize = AtomicUsize::new(0);
+    static PREVIOUS_TERM: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+
+    // only an atomic store is done here, anything else isn't safe in a signal handler.
+    extern "C" fn on_signal(_: c_int) {
+        RECEIVED.store(true, Ordering::SeqCst);
+    }
+
+    pub fn install() {
+        RECEIVED.store(false, Ordering::SeqCst);
+        let handler = on_signal as extern "C" fn(c_int) as usize;
+
+        // SAFETY: `on_signal` only touches an atomic, which is async-signal-safe.
+        unsafe {
+            PREVIOUS_INT.store(signal(SIGINT, handler), Ordering::SeqCst);
+            PREVIOUS_TERM.store(signal(SIGTERM, handler), Ordering::SeqCst);
+        }
+    }
+
+    pub fn restore() {
+        // SAFETY: the previous values were returned by `signal` for the same signals.
+        unsafe {
+            signal(SIGINT, PREVIOUS_INT.load(Ordering::SeqCst));
+            signal(SIGTERM, PREVIOUS_TERM.load(Ordering::SeqCst));
+        }
+    }
+
+    pub fn take() -> bool {
+        RECEIVED.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Signals are only handled on unix, elsewhere only `ShutdownHandle` stops the server.
+#[cfg(not(unix))]
+mod signals {
+    pub fn install() {}
+    pub fn restore() {}
+    pub fn take() -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod shutdown_tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    use crate::HTTPServer;
+
+    /// Starts listening on another thread, the receiver gets the result once `listen` returns.
+    fn listen() -> (ShutdownHandle, mpsc::Receiver<bool>) {
+        let mut server = HTTPServer::new(2);
+        server.config.handle_signals = false;
+        let handle = server.shutdown_handle();
+
+        let (done, returned) = mpsc::channel();
+        let listening = handle.clone();
+        thread::spawn(move || {
+            let result = server.listen("127.0.0.1:0");
+            done.send(result.is_ok() && !listening.is_shutdown())
+                .unwrap();
+        });
+
+        (handle, returned)
+    }
+
+    #[test]
+    fn stops_listening() {
+        let (handle, returned) = listen();
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown();
+        assert_eq!(returned.recv_timeout(Duration::from_secs(5)), Ok(true));
+
+        // requested before the listener is set up, `listen` doesn't wait for a connection.
+        let (handle, returned) = listen();
+        handle.shutdown();
+        assert_eq!(returned.recv_timeout(Duration::from_secs(5)), Ok(true));
+    }
+}
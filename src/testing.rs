@@ -0,0 +1,77 @@
+//! Helpers shared by the tests of different modules.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::config::ServerConfig;
+use crate::pool::TimedStream;
+use crate::request::{ConnectionReader, Request};
+use crate::response::Response;
+
+/// Opens a connection and sends `raw` on it, returns the client and the server ends.
+pub fn connect(raw: &str) -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(raw.as_bytes()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+
+    (client, server)
+}
+
+/// The server end of a connection, set up like a worker does it.
+pub struct Connection {
+    pub client: TcpStream,
+    pub stream: Rc<RefCell<TcpStream>>,
+    pub reader: ConnectionReader,
+    pub config: ServerConfig,
+    statuses: Arc<HashMap<u16, String>>,
+    mimes: Arc<HashMap<&'static str, &'static str>>,
+}
+
+impl Connection {
+    /// Connects and sends `raw`, the config can be changed before reading requests.
+    pub fn open(raw: &str) -> Self {
+        let (client, server) = connect(raw);
+        let reader = Rc::new(RefCell::new(BufReader::new(TimedStream::new(
+            server.try_clone().unwrap(),
+        ))));
+
+        Connection {
+            client,
+            stream: Rc::new(RefCell::new(server)),
+            reader,
+            config: ServerConfig::default(),
+            statuses: Arc::new(crate::import_status_messages()),
+            mimes: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Reads the next request sent by the client.
+    pub fn request(&self) -> Request {
+        let mut head = Vec::new();
+        Request::read_head(&self.reader, &self.config, &mut head).unwrap();
+        Request::parse(head, self.stream.clone(), self.reader.clone(), &self.config).unwrap()
+    }
+
+    pub fn response(&self) -> Response {
+        Response::new(
+            200,
+            Arc::downgrade(&self.statuses),
+            self.stream.clone(),
+            Arc::downgrade(&self.mimes),
+        )
+    }
+
+    /// Closes the server end and returns everything the client received.
+    pub fn received(&mut self) -> String {
+        self.stream.borrow().shutdown(Shutdown::Write).unwrap();
+
+        let mut out = String::new();
+        self.client.read_to_string(&mut out).unwrap();
+        out
+    }
+}
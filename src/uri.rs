@@ -0,0 +1,124 @@
+use std::io::Error as err;
+
+use crate::error::HttpError;
+
+/// Turns a raw request path into the form used for routing.
+///
+/// The path is percent-decoded first and then dot segments (`.` and `..`) and empty segments are removed,
+/// decoding first means `%2e%2e` can't be used to escape the root.
+///
+/// ## Fails:
+/// With a `400` if the path isn't absolute, has an invalid escape, an encoded NUL or invalid UTF-8.
+pub fn normalize_path(raw: &str) -> Result<String, err> {
+    if raw == "*" {
+        return Ok(raw.to_string());
+    }
+
+    let raw = strip_authority(raw);
+    if !raw.starts_with('/') {
+        return Err(HttpError::new(400, "Request path must be absolute").into());
+    }
+
+    Ok(remove_dot_segments(&percent_decode(raw)?))
+}
+
+/// Strictly percent-decodes `input`, unlike query strings `+` is left as it is.
+///
+/// ## Fails:
+/// With a `400` on invalid escapes, encoded NULs or if the result isn't valid UTF-8.
+pub fn percent_decode(input: &str) -> Result<String, err> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = bytes
+                .get(i + 1..i + 3)
+                .and_then(|pair| hex_pair(pair[0], pair[1]))
+                .ok_or(HttpError::new(400, "Invalid percent-encoding in path"))?;
+
+            if byte == 0 {
+                return Err(HttpError::new(400, "Encoded NUL in path").into());
+            }
+
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| HttpError::new(400, "Path is not valid UTF-8").into())
+}
+
+/// Removes `.`, `..` and empty segments from an absolute path, based on RFC 3986 section 5.2.4.
+///
+/// `..` never goes above the root and a trailing slash is kept.
+pub fn remove_dot_segments(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    let trailing = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+
+    if trailing && !segments.is_empty() {
+        normalized.push('/');
+    }
+
+    normalized
+}
+
+/// Decodes two hex digits into a byte.
+pub(crate) fn hex_pair(high: u8, low: u8) -> Option<u8> {
+    let high = (high as char).to_digit(16)?;
+    let low = (low as char).to_digit(16)?;
+
+    Some((high * 16 + low) as u8)
+}
+
+/// Removes the scheme and authority from an absolute-form target like `http://host/path`.
+fn strip_authority(target: &str) -> &str {
+    let rest = match target.split_once("://") {
+        Some((scheme, rest)) if !scheme.contains('/') => rest,
+        _ => return target,
+    };
+
+    match rest.find('/') {
+        Some(index) => &rest[index..],
+        None => "/",
+    }
+}
+
+#[cfg(test)]
+mod uri_tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize_path("/caf%C3%A9.txt").unwrap(), "/café.txt");
+        assert_eq!(normalize_path("/a/./b").unwrap(), "/a/b");
+        assert_eq!(normalize_path("/a//b/").unwrap(), "/a/b/");
+        assert_eq!(normalize_path("/a/b/../../..").unwrap(), "/");
+        assert_eq!(normalize_path("/%2e%2e/etc/passwd").unwrap(), "/etc/passwd");
+        assert_eq!(normalize_path("http://example.com/x").unwrap(), "/x");
+    }
+
+    #[test]
+    fn rejects_bad_paths() {
+        for raw in ["/a%00b", "/%FF", "/%zz", "relative"] {
+            let error = normalize_path(raw).unwrap_err();
+            assert_eq!(HttpError::status_of(&error), Some(400));
+        }
+    }
+}